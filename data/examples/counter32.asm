@@ -0,0 +1,8 @@
+; A 32-bit counter shown on two lamp rows: P0 is the high word, P1 is the low word.
+        mov r0, 0           ; high word
+        mov r1, 0xFFF0      ; low word, starts close to wrapping around
+loop:   add r1, 1           ; sets the carry when the low word wraps around...
+        adc r0, 0           ; ...and the carry goes to the high word
+        wrt p0, r0
+        wrt p1, r1
+        jmp @loop
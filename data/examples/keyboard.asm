@@ -0,0 +1,10 @@
+; Click the lamps to take the focus away from the editor, then press some keys.
+; P0 shows the keys held down: arrows, space, enter and escape.
+; P1 collects the bits of every key code pressed so far.
+        mov r1, 0
+loop:   read r0, p14        ; keys held down
+        wrt p0, r0
+        read r0, p15        ; next pressed key, 0 if there is none
+        or r1, r0
+        wrt p1, r1
+        jmp @loop
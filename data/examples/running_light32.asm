@@ -0,0 +1,10 @@
+; A light running across 32 lamps of two rows: P0 is the high word, P1 is the low word.
+; Rotating through the carry chains both words together, so the light passes
+; from the top of P1 to the bottom of P0, hiding in the carry for one step.
+        mov r0, 0           ; high word
+        mov r1, 1           ; low word
+loop:   wrt p0, r0
+        wrt p1, r1
+        rcl r1, 1           ; the top bit of the low word goes to the carry...
+        rcl r0, 1           ; ...and from the carry to the bottom bit of the high word
+        jmp @loop
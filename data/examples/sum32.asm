@@ -0,0 +1,11 @@
+; Adds two 32-bit numbers and shows the sum on two lamp rows:
+; P0 is the high word, P1 is the low word.
+        mov r0, 0x0001      ; high word of the first number
+        mov r1, 0xF000      ; low word of the first number
+        mov r2, 0x0002      ; high word of the second number
+        mov r3, 0x2345      ; low word of the second number
+        add r1, r3          ; low words first, the carry is kept...
+        adc r0, r2          ; ...and added to the high words
+        wrt p0, r0
+        wrt p1, r1
+        stop
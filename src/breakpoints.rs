@@ -0,0 +1,411 @@
+// Breakpoints with optional conditions like `r0 == 10`, `[0x800] & 0xff != 0` or `hits > 5`.
+//
+// Conditions are integer expressions with Rust operators and precedence.
+// Variables: the general registers of the machine (`r0`..`r3` by default), `sp`, `pc`, `ps`,
+// the output ports `p0`..`p255` and `hits`,
+// the number of times the breakpoint address was reached, including this one.
+// `[addr]` is the word at `addr`. Values are unsigned, a non-zero condition is true.
+
+use crate::executor::ProgramExecutor;
+use crate::machine::MachineConfig;
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Variable {
+    Register(u8),
+    Pc,
+    Ps,
+    Port(u8),
+    Hits,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+    BitNot,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BinaryOp {
+    Mul,
+    Div,
+    Rem,
+    Add,
+    Sub,
+    Shl,
+    Shr,
+    BitAnd,
+    BitXor,
+    BitOr,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+// Binary operators with their precedence, longer ones first so that `<=` isn't read as `<`.
+const BINARY_OPERATORS: [(&str, BinaryOp, u8); 18] = [
+    ("<<", BinaryOp::Shl, 8),
+    (">>", BinaryOp::Shr, 8),
+    ("==", BinaryOp::Eq, 3),
+    ("!=", BinaryOp::Ne, 3),
+    ("<=", BinaryOp::Le, 3),
+    (">=", BinaryOp::Ge, 3),
+    ("&&", BinaryOp::And, 2),
+    ("||", BinaryOp::Or, 1),
+    ("*", BinaryOp::Mul, 10),
+    ("/", BinaryOp::Div, 10),
+    ("%", BinaryOp::Rem, 10),
+    ("+", BinaryOp::Add, 9),
+    ("-", BinaryOp::Sub, 9),
+    ("&", BinaryOp::BitAnd, 7),
+    ("^", BinaryOp::BitXor, 6),
+    ("|", BinaryOp::BitOr, 5),
+    ("<", BinaryOp::Lt, 3),
+    (">", BinaryOp::Gt, 3),
+];
+
+#[derive(Clone, Debug)]
+pub enum Expr {
+    Number(u64),
+    Variable(Variable),
+    Memory(Box<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    pub fn parse(text: &str, config: &MachineConfig) -> Result<Expr, ExprError> {
+        let mut parser = Parser {
+            text,
+            pos: 0,
+            general_registers: config.general_registers,
+        };
+        let expr = parser.parse_binary(0)?;
+        parser.skip_whitespace();
+        if parser.pos < text.len() {
+            return Err(parser.error_at(parser.pos..text.len(), "Unexpected input"));
+        }
+        Ok(expr)
+    }
+
+    pub fn eval(&self, executor: &ProgramExecutor, hits: u64) -> u64 {
+        match self {
+            Expr::Number(num) => *num,
+            Expr::Variable(variable) => match *variable {
+                Variable::Register(reg) => executor.registers[reg as usize] as u64,
+                Variable::Pc => executor.curr_addr as u64,
+                Variable::Ps => executor.program_state_reg as u64,
+                // Ports the machine doesn't have read as 0.
+                Variable::Port(port) => {
+                    executor.display.get(port as usize).copied().unwrap_or(0) as u64
+                }
+                Variable::Hits => hits,
+            },
+            // Addresses outside of memory read as 0.
+            Expr::Memory(addr) => {
+                let addr = addr.eval(executor, hits);
+                u16::try_from(addr)
+                    .ok()
+                    .and_then(|addr| executor.read_u16(addr).ok())
+                    .unwrap_or(0) as u64
+            }
+            Expr::Unary(op, expr) => {
+                let value = expr.eval(executor, hits);
+                match op {
+                    UnaryOp::Neg => value.wrapping_neg(),
+                    UnaryOp::Not => (value == 0) as u64,
+                    UnaryOp::BitNot => !value,
+                }
+            }
+            Expr::Binary(op, a, b) => {
+                let a = a.eval(executor, hits);
+                // `&&` and `||` don't evaluate the right side when it doesn't matter.
+                match op {
+                    BinaryOp::And if a == 0 => return 0,
+                    BinaryOp::Or if a != 0 => return 1,
+                    _ => {}
+                }
+                let b = b.eval(executor, hits);
+                match op {
+                    BinaryOp::Mul => a.wrapping_mul(b),
+                    // Division by zero gives 0, like `div` with strict division off.
+                    BinaryOp::Div => a.checked_div(b).unwrap_or(0),
+                    BinaryOp::Rem => a.checked_rem(b).unwrap_or(0),
+                    BinaryOp::Add => a.wrapping_add(b),
+                    BinaryOp::Sub => a.wrapping_sub(b),
+                    BinaryOp::Shl => a.checked_shl(b as u32).unwrap_or(0),
+                    BinaryOp::Shr => a.checked_shr(b as u32).unwrap_or(0),
+                    BinaryOp::BitAnd => a & b,
+                    BinaryOp::BitXor => a ^ b,
+                    BinaryOp::BitOr => a | b,
+                    BinaryOp::Eq => (a == b) as u64,
+                    BinaryOp::Ne => (a != b) as u64,
+                    BinaryOp::Lt => (a < b) as u64,
+                    BinaryOp::Le => (a <= b) as u64,
+                    BinaryOp::Gt => (a > b) as u64,
+                    BinaryOp::Ge => (a >= b) as u64,
+                    BinaryOp::And | BinaryOp::Or => (b != 0) as u64,
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ExprError {
+    // Byte range of the erroneous part of the condition.
+    pub span: Range<usize>,
+    pub message: String,
+}
+
+impl Display for ExprError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "column {}: {}", self.span.start + 1, self.message)
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+    general_registers: usize,
+}
+
+impl Parser<'_> {
+    fn error_at(&self, span: Range<usize>, message: &str) -> ExprError {
+        ExprError {
+            span,
+            message: message.into(),
+        }
+    }
+
+    fn rest(&self) -> &str {
+        &self.text[self.pos..]
+    }
+
+    fn next_char_span(&self) -> Range<usize> {
+        self.pos..self.pos + self.rest().chars().next().map_or(0, char::len_utf8)
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    // Consumes `token` if the input continues with it.
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        let found = self.rest().starts_with(token);
+        if found {
+            self.pos += token.len();
+        }
+        found
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), ExprError> {
+        if self.eat(token) {
+            return Ok(());
+        }
+        Err(self.error_at(self.next_char_span(), &format!("Expected `{token}`")))
+    }
+
+    // Precedence climbing: parses operators that bind at least as tight as `min_precedence`.
+    fn parse_binary(&mut self, min_precedence: u8) -> Result<Expr, ExprError> {
+        let mut lhs = self.parse_unary()?;
+        loop {
+            self.skip_whitespace();
+            let Some(&(token, op, precedence)) = BINARY_OPERATORS
+                .iter()
+                .find(|(token, ..)| self.rest().starts_with(token))
+            else {
+                return Ok(lhs);
+            };
+            if precedence < min_precedence {
+                return Ok(lhs);
+            }
+            self.pos += token.len();
+            let rhs = self.parse_binary(precedence + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ExprError> {
+        for (token, op) in [
+            ("-", UnaryOp::Neg),
+            ("!", UnaryOp::Not),
+            ("~", UnaryOp::BitNot),
+        ] {
+            // `!=` is never at the start of an operand, so `!` is safe to eat here.
+            if self.eat(token) {
+                return Ok(Expr::Unary(op, Box::new(self.parse_unary()?)));
+            }
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, ExprError> {
+        if self.eat("(") {
+            let expr = self.parse_binary(0)?;
+            self.expect(")")?;
+            return Ok(expr);
+        }
+        if self.eat("[") {
+            let expr = self.parse_binary(0)?;
+            self.expect("]")?;
+            return Ok(Expr::Memory(Box::new(expr)));
+        }
+        self.skip_whitespace();
+        let start = self.pos;
+        let len = self
+            .rest()
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(self.rest().len());
+        if len == 0 {
+            return Err(self.error_at(
+                self.next_char_span(),
+                "Expected a number, a variable or `(`",
+            ));
+        }
+        self.pos += len;
+        let word = self.text[start..self.pos].to_ascii_lowercase();
+        let span = start..self.pos;
+        if word.starts_with(|c: char| c.is_ascii_digit()) {
+            let number = match word.strip_prefix("0x") {
+                Some(hex) => u64::from_str_radix(hex, 16),
+                None => word.parse(),
+            };
+            return number
+                .map(Expr::Number)
+                .map_err(|_| self.error_at(span, "Invalid number"));
+        }
+        let variable = match word.as_str() {
+            "sp" => Variable::Register(4),
+            "pc" => Variable::Pc,
+            "ps" => Variable::Ps,
+            "hits" => Variable::Hits,
+            _ => {
+                let index = |prefix: char, count: usize| {
+                    word.strip_prefix(prefix)
+                        .and_then(|i| i.parse::<u8>().ok())
+                        .filter(|&i| (i as usize) < count)
+                };
+                if let Some(reg) = index('r', self.general_registers) {
+                    Variable::Register(reg)
+                } else if let Some(port) = index('p', 256) {
+                    Variable::Port(port)
+                } else {
+                    return Err(self.error_at(span, &format!("Unknown variable `{word}`")));
+                }
+            }
+        };
+        Ok(Expr::Variable(variable))
+    }
+}
+
+pub struct Breakpoint {
+    pub address: usize,
+    // The source text of `condition`, for showing the breakpoint.
+    pub condition_text: String,
+    pub condition: Option<Expr>,
+    pub hits: u64,
+}
+
+impl Breakpoint {
+    // An empty condition means an unconditional breakpoint.
+    pub fn new(
+        address: usize,
+        condition_text: &str,
+        config: &MachineConfig,
+    ) -> Result<Self, ExprError> {
+        let condition_text = condition_text.trim();
+        let condition = if condition_text.is_empty() {
+            None
+        } else {
+            Some(Expr::parse(condition_text, config)?)
+        };
+        Ok(Self {
+            address,
+            condition_text: condition_text.into(),
+            condition,
+            hits: 0,
+        })
+    }
+}
+
+impl Display for Breakpoint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "0x{:x}", self.address)?;
+        if self.condition.is_some() {
+            write!(f, " if {}", self.condition_text)?;
+        }
+        write!(f, " (hits: {})", self.hits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(text: &str, executor: &ProgramExecutor, hits: u64) -> u64 {
+        Expr::parse(text, &executor.config())
+            .unwrap()
+            .eval(executor, hits)
+    }
+
+    fn error(text: &str, config: &MachineConfig) -> String {
+        Expr::parse(text, config).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn operators_have_rust_precedence() {
+        let mut executor = ProgramExecutor::default();
+        executor.write_u16(0x800, 0x1234).unwrap();
+        assert_eq!(eval("[0x800] & 0xff != 0", &executor, 0), 1);
+        assert_eq!(eval("[0x800] & 0xff0000 == 0", &executor, 0), 1);
+
+        executor.registers[0] = 1;
+        assert_eq!(eval("hits > 5 && r0 == 1", &executor, 6), 1);
+        assert_eq!(eval("hits > 5 && r0 == 1", &executor, 5), 0);
+        assert_eq!(eval("r0 == 1 || hits > 5 && r0 == 2", &executor, 0), 1);
+        assert_eq!(eval("1 + 2 * 3 << 1", &executor, 0), 14);
+        assert_eq!(eval("10 - 4 - 3", &executor, 0), 3);
+        assert_eq!(eval("-r0 + 2", &executor, 0), 1);
+    }
+
+    #[test]
+    fn parentheses_group_expressions() {
+        let mut executor = ProgramExecutor::default();
+        executor.write_u16(0x802, 7).unwrap();
+        assert_eq!(eval("(1 + 2) * 3", &executor, 0), 9);
+        assert_eq!(eval("10 - (4 - 3)", &executor, 0), 9);
+        assert_eq!(eval("!(hits > 5 && (r0 == 0))", &executor, 6), 0);
+        assert_eq!(eval("[0x800 + (1 + 1)] * 2", &executor, 0), 14);
+    }
+
+    #[test]
+    fn errors_point_at_the_column() {
+        let config = MachineConfig::default();
+        assert_eq!(error("r0 $ 1", &config), "column 4: Unexpected input");
+        assert_eq!(error("(r0 + 1", &config), "column 8: Expected `)`");
+        assert_eq!(error("[r0 + 1)", &config), "column 8: Expected `]`");
+        assert_eq!(
+            error("r0 ==  ", &config),
+            "column 8: Expected a number, a variable or `(`"
+        );
+        assert_eq!(error("hits > 0x", &config), "column 8: Invalid number");
+        assert_eq!(error("r0 + r4", &config), "column 6: Unknown variable `r4`");
+    }
+
+    #[test]
+    fn registers_depend_on_the_machine() {
+        let config = MachineConfig::preset("minimal").unwrap();
+        assert!(Expr::parse("r1 + sp", &config).is_ok());
+        assert_eq!(error("r2 == 0", &config), "column 1: Unknown variable `r2`");
+    }
+}
@@ -0,0 +1,293 @@
+// Command line runner: `rustanel <program.asm> [options]`.
+// Runs the program without a window, then prints the registers and the lamps.
+// `rustanel --fuzz <runs> [<seed>]` runs random memory images instead, see `fuzz`.
+// `--protect-code` makes writes to the instructions of the program runtime errors,
+// `--stack <size>` reserves the last `size` bytes of memory for the stack.
+// `--resume <snapshot>` continues a saved machine instead of starting a program,
+// `--save <snapshot>` saves the machine after the run. Steps are counted from the start
+// of this run in both cases.
+// `--machine <preset>` picks one of `machine::PRESETS`, a snapshot brings its own machine.
+// `--trace <file>` writes the executed instructions as CSV to `.csv` files and as JSON Lines
+// otherwise, `--trace-range <start>..<end>` traces only the instructions in the range.
+//
+// A key script has one event per line: `<step> press|release <key>`, where `<step>` is the
+// number of instructions executed before the event and `<key>` is a name accepted by
+// `key_code`, e.g. `12 press ArrowLeft`. Empty lines and `;` comments are skipped.
+
+use crate::compiler::Compiler;
+use crate::devices::key_code;
+use crate::executor::{CodeProtection, ProgramExecutor, StackRegion};
+use crate::fuzz;
+use crate::machine::{MachineConfig, LAMPS_PER_PORT, PRESETS, SP};
+use crate::snapshot::Snapshot;
+use crate::trace::{parse_address_range, Tracer};
+use std::ops::Range;
+
+const USAGE: &str = "Usage: rustanel <program.asm> | --resume <snapshot> [--keys <script>] \
+    [--machine <preset>] [--steps <n>] [--protect-code] [--stack <size>] [--save <snapshot>] \
+    [--trace <file>] \
+    [--trace-range <start>..<end>]...\n   \
+    or: rustanel --fuzz [<runs> [<seed>]]";
+const DEFAULT_MAX_STEPS: u64 = 1_000_000;
+
+struct KeyEvent {
+    step: u64,
+    code: u16,
+    pressed: bool,
+}
+
+// Where the machine comes from.
+enum Start {
+    Program(String),
+    Snapshot(String),
+}
+
+struct Options {
+    start: Start,
+    machine: MachineConfig,
+    keys_path: Option<String>,
+    max_steps: u64,
+    protect_code: bool,
+    stack_size: Option<usize>,
+    save_path: Option<String>,
+    trace_path: Option<String>,
+    trace_ranges: Vec<Range<usize>>,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut start = None;
+    let mut machine = MachineConfig::default();
+    let mut keys_path = None;
+    let mut max_steps = DEFAULT_MAX_STEPS;
+    let mut protect_code = false;
+    let mut stack_size = None;
+    let mut save_path = None;
+    let mut trace_path = None;
+    let mut trace_ranges = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--keys" => keys_path = Some(args.next().ok_or(USAGE)?.clone()),
+            "--steps" => {
+                let steps = args.next().ok_or(USAGE)?;
+                max_steps = steps
+                    .parse()
+                    .map_err(|_| format!("Invalid number of steps: `{steps}`"))?;
+            }
+            "--machine" => {
+                let name = args.next().ok_or(USAGE)?;
+                machine = MachineConfig::preset(name).ok_or_else(|| {
+                    let names: Vec<&str> = PRESETS.iter().map(|(name, _)| *name).collect();
+                    format!("Unknown machine `{name}`, expected {}", names.join(", "))
+                })?;
+            }
+            "--protect-code" => protect_code = true,
+            "--stack" => {
+                let size = args.next().ok_or(USAGE)?;
+                stack_size = Some(
+                    size.parse()
+                        .ok()
+                        .filter(|&size| size >= 2)
+                        .ok_or_else(|| format!("Invalid stack size: `{size}`"))?,
+                );
+            }
+            "--resume" if start.is_none() => {
+                start = Some(Start::Snapshot(args.next().ok_or(USAGE)?.clone()))
+            }
+            "--save" => save_path = Some(args.next().ok_or(USAGE)?.clone()),
+            "--trace" => trace_path = Some(args.next().ok_or(USAGE)?.clone()),
+            "--trace-range" => {
+                let range = args.next().ok_or(USAGE)?;
+                trace_ranges.push(
+                    parse_address_range(range)
+                        .ok_or_else(|| format!("Invalid address range: `{range}`"))?,
+                );
+            }
+            _ if start.is_none() && !arg.starts_with("--") => {
+                start = Some(Start::Program(arg.clone()))
+            }
+            _ => return Err(USAGE.into()),
+        }
+    }
+    Ok(Options {
+        start: start.ok_or(USAGE)?,
+        machine,
+        keys_path,
+        max_steps,
+        protect_code,
+        stack_size,
+        save_path,
+        trace_path,
+        trace_ranges,
+    })
+}
+
+fn parse_key_script(script: &str) -> Result<Vec<KeyEvent>, String> {
+    let mut events = vec![];
+    for (line_i, line) in script.lines().enumerate() {
+        let line = line.split(';').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error = || format!("line {}: Invalid key event: `{line}`", line_i + 1);
+        let [step, action, key] = line.split_whitespace().collect::<Vec<_>>()[..] else {
+            return Err(error());
+        };
+        events.push(KeyEvent {
+            step: step.parse().map_err(|_| error())?,
+            code: key_code(key).ok_or_else(error)?,
+            pressed: match action {
+                "press" => true,
+                "release" => false,
+                _ => return Err(error()),
+            },
+        });
+    }
+    // Events of the same step keep their order.
+    events.sort_by_key(|event| event.step);
+    Ok(events)
+}
+
+fn print_state(executor: &ProgramExecutor) {
+    let config = executor.config();
+    for (i, reg) in executor.registers.iter().enumerate() {
+        let name = if i == SP as usize {
+            "SP".to_string()
+        } else if config.has_register(i as u8) {
+            format!("R{i}")
+        } else {
+            continue;
+        };
+        println!("{name}: {reg:#06x}");
+    }
+    println!("PC: {:#06x}", executor.curr_addr);
+    println!("PS: {:#06x}", executor.program_state_reg);
+    println!(
+        "Executed {} instructions in {} cycles",
+        executor.executed_instructions, executor.cycles
+    );
+    let label_width = format!("P{}", config.ports() - 1).len();
+    for (row, ports) in executor.display.chunks(config.ports_per_row()).enumerate() {
+        let lamps: String = ports
+            .iter()
+            .flat_map(|port| (0..LAMPS_PER_PORT).map(move |j| (port >> (15 - j)) & 1 == 1))
+            .map(|on| if on { '#' } else { '.' })
+            .collect();
+        let label = format!("P{}", row * config.ports_per_row());
+        println!("{label:<label_width$} {lamps}");
+    }
+}
+
+fn compile(code: &str, machine: MachineConfig) -> Result<Compiler, String> {
+    let mut compiler = Compiler::build();
+    compiler.set_machine_config(machine);
+    compiler.compile_code(code);
+    if !compiler.errors.is_empty() {
+        let mut messages: Vec<String> = compiler
+            .errors
+            .iter()
+            .map(|(_, err)| err.to_string())
+            .collect();
+        messages.sort_unstable();
+        return Err(messages.join("\n"));
+    }
+    Ok(compiler)
+}
+
+fn run_program(options: &Options) -> Result<(), String> {
+    let read = |path: &str| {
+        std::fs::read_to_string(path).map_err(|err| format!("Can't read `{path}`: {err}"))
+    };
+    let key_events = match &options.keys_path {
+        Some(path) => parse_key_script(&read(path)?)?,
+        None => vec![],
+    };
+    let stack_region = |memory_size: usize| match options.stack_size {
+        Some(size) if size > StackRegion::top(memory_size) => Err(format!(
+            "The stack doesn't fit in {memory_size} bytes of memory"
+        )),
+        size => Ok(size.map(|size| StackRegion::at_the_end_of_memory(size, memory_size))),
+    };
+    let mut executor = ProgramExecutor::default();
+    match &options.start {
+        Start::Program(path) => {
+            let compiler = compile(&read(path)?, options.machine)?;
+            executor.set_machine_config(options.machine);
+            executor.stack_region = stack_region(options.machine.memory_size)?;
+            executor.prepare_for_a_new_run();
+            executor.load_program(&compiler.program, compiler.code_size);
+        }
+        Start::Snapshot(path) => {
+            executor.restore(&Snapshot::load(path)?);
+            executor.stack_region = stack_region(executor.config().memory_size)?;
+        }
+    }
+    let first_step = executor.executed_instructions;
+    executor.max_instructions = Some(first_step.saturating_add(options.max_steps));
+    if options.protect_code {
+        executor.code_protection = CodeProtection::ReadOnly;
+    }
+    if options.trace_path.is_some() {
+        executor.tracer = Some(Tracer::with_address_ranges(options.trace_ranges.clone()));
+    }
+    let mut key_events = key_events.iter().peekable();
+    let mut result = Ok(());
+    while !executor.has_finished {
+        let step = executor.executed_instructions - first_step;
+        while let Some(event) = key_events.next_if(|event| event.step <= step) {
+            if event.pressed {
+                executor.keyboard.press(event.code);
+            } else {
+                executor.keyboard.release(event.code);
+            }
+        }
+        // A program waiting for a key isn't stuck while the script still has keys to press.
+        executor.detect_loops = key_events.peek().is_none();
+        // The failed instruction isn't executed, so a saved machine can continue from it,
+        // e.g. after the steps ran out.
+        if let Err(err) = executor.execute_next_instruction() {
+            result = Err(err.to_string());
+            break;
+        }
+    }
+    print_state(&executor);
+    if let Some(path) = &options.save_path {
+        executor.snapshot().save(path)?;
+    }
+    if let (Some(path), Some(tracer)) = (&options.trace_path, &executor.tracer) {
+        tracer.export(path)?;
+    }
+    result
+}
+
+fn run_fuzzer(args: &[String]) -> Result<(), String> {
+    let parse = |arg: Option<&String>, default: u64| {
+        arg.map_or(Ok(default), |arg| {
+            arg.parse().map_err(|_| format!("Invalid number: `{arg}`"))
+        })
+    };
+    if args.len() > 2 {
+        return Err(USAGE.into());
+    }
+    let runs = parse(args.first(), 1000)?;
+    let seed = parse(args.get(1), 0)?;
+    fuzz::run(runs, seed)
+        .map_err(|seed| format!("The executor panicked, rerun with `--fuzz 1 {seed}`"))?;
+    println!("{runs} random runs without panics");
+    Ok(())
+}
+
+// Returns the exit code.
+pub fn run(args: &[String]) -> i32 {
+    let result = match args.split_first() {
+        Some((first, rest)) if first == "--fuzz" => run_fuzzer(rest),
+        _ => parse_args(args).and_then(|options| run_program(&options)),
+    };
+    match result {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("{err}");
+            1
+        }
+    }
+}
@@ -1,25 +1,41 @@
 use crate::highlighting::wrapping_parse;
+use crate::instruction_registry::InstructionRegistry;
 use crate::instruction_set::{
-    get_expected_operand_types_string, AcceptedOperandTypes, InstructionOperand,
-    InstructionOperands, ADDR_INC_MASK, ADDR_MASK, INSTRUCTION_SET, NUMBER_MASK,
-    NUMBER_OPERAND_CODE, PORT_MASK, REG_MASK,
+    get_expected_operand_types_string, AcceptedOperandTypes, InstructionInfo, InstructionOperand,
+    ABSOLUTE_MASK, ABSOLUTE_MODE, ADDR_DEC_MASK, ADDR_DEC_MODE, ADDR_INC_MASK, ADDR_MASK,
+    EXTENDED_OPERANDS_FLAG, HIGH_PORT_MODES, INDEXED_MASK, INDEXED_MODE, NUMBER_MASK,
+    NUMBER_OPERAND_CODE, PLAIN_MODE, PORT_MASK, REG_MASK,
 };
+use crate::machine::MachineConfig;
 use eframe::egui::ahash::{HashSet, HashSetExt};
 use eframe::epaint::ahash::{HashMap, HashMapExt};
 use lazy_regex::{regex_captures, regex_is_match};
 use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
 use std::ops::Range;
-
-pub const MAX_PROGRAM_SIZE: usize = 0x1000;
+use std::rc::Rc;
 
 pub struct Compiler {
-    instruction_codes: HashMap<&'static str, u8>,
-    pub program: [u8; MAX_PROGRAM_SIZE],
-    label_mentions_in_program: Vec<(String, (usize, usize))>,
+    instructions: Rc<InstructionRegistry>,
+    // Several opcodes may share a name, e.g. `bts` for registers and `bts` for ports.
+    instruction_codes: HashMap<&'static str, Vec<u8>>,
+    // Sorted and without duplicates, shared with the highlighter.
+    instruction_names: Rc<Vec<&'static str>>,
+    // The whole memory of the machine, the program is at the start.
+    pub program: Vec<u8>,
+    // Instructions take the first `code_size` bytes of `program`.
+    pub code_size: usize,
+    label_mentions_in_program: Vec<(String, Range<usize>, (usize, usize))>,
     line_addresses: Vec<usize>,
     line_i: usize,
-    pub errors: ErrorsHighlightInfo,
+    // Shared with the UI, so that it doesn't have to copy errors every frame.
+    pub errors: Rc<ErrorsHighlightInfo>,
+    // The code `program` and `errors` were produced from.
+    compiled_code: Option<String>,
+    // Strict mode: `Loop` and `loop` are two different labels.
+    case_sensitive_labels: bool,
+    // Registers, ports and memory available to programs.
+    machine_config: MachineConfig,
 }
 
 #[derive(Debug, Hash, Clone)]
@@ -27,14 +43,17 @@ pub enum CompilationError {
     UnknownInstruction {
         line: usize,
         instruction: String,
+        suggestion: Option<String>,
     },
     NoLabelWithSuchName {
         line: usize,
         name: String,
+        suggestion: Option<String>,
     },
     InvalidOperand {
         line: usize,
         operand: String,
+        suggestion: Option<String>,
     },
     WrongNumberOfOperands {
         line: usize,
@@ -64,13 +83,15 @@ pub type CompilationResult<T> = Result<T, CompilationError>;
 impl Display for CompilationError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            CompilationError::UnknownInstruction { line, instruction } => {
+            CompilationError::UnknownInstruction {
+                line, instruction, ..
+            } => {
                 write!(f, "line {}: Unknown instruction: `{instruction}`", line + 1)
             }
-            CompilationError::NoLabelWithSuchName { line, name } => {
+            CompilationError::NoLabelWithSuchName { line, name, .. } => {
                 write!(f, "line {}: No label with such name: `{name}`", line + 1)
             }
-            CompilationError::InvalidOperand { line, operand } => {
+            CompilationError::InvalidOperand { line, operand, .. } => {
                 write!(f, "line {}: Invalid operand: `{operand}`", line + 1)
             }
             CompilationError::WrongNumberOfOperands {
@@ -104,46 +125,174 @@ impl Display for CompilationError {
             CompilationError::InvalidLabelName { line, name } => {
                 write!(f, "line {}: `{name}` is not a correct label name", line + 1)
             }
+        }?;
+        if let Some(suggestion) = self.suggestion() {
+            write!(f, ", did you mean `{suggestion}`?")?;
         }
+        Ok(())
     }
 }
 
 impl Error for CompilationError {}
 
+impl CompilationError {
+    /// Text that is likely meant instead of the erroneous token, if there is a close enough one.
+    pub fn suggestion(&self) -> Option<&str> {
+        match self {
+            CompilationError::UnknownInstruction { suggestion, .. }
+            | CompilationError::NoLabelWithSuchName { suggestion, .. }
+            | CompilationError::InvalidOperand { suggestion, .. } => suggestion.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+// Optimal string alignment distance: the Levenshtein distance that also counts swapping
+// two adjacent characters as a single edit, which is the most common typo (`mvo` -> `mov`).
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a = a.chars().collect::<Vec<char>>();
+    let b = b.chars().collect::<Vec<char>>();
+    let mut dist = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in dist.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    dist[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = (a[i - 1] != b[j - 1]) as usize;
+            dist[i][j] = (dist[i - 1][j] + 1)
+                .min(dist[i][j - 1] + 1)
+                .min(dist[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                dist[i][j] = dist[i][j].min(dist[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    dist[a.len()][b.len()]
+}
+
+// Finds the candidate closest to `word`, if it is close enough to be a plausible typo.
+fn closest_match<'a>(word: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let max_distance = (word.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(word, candidate), candidate))
+        .filter(|&(distance, _)| distance <= max_distance)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
 pub type ErrorsHighlightInfo = Vec<(Range<usize>, CompilationError)>;
 
+// Binary code of an instruction and the labels it mentions:
+// their names, spans and offsets of the words to put their addresses in.
+type AssembledInstruction = (Vec<u8>, Vec<(String, Range<usize>, usize)>);
+
+// A piece of the source code that remembers its byte offset in the whole program,
+// so that errors can point exactly at the offending text.
+#[derive(Clone, Copy, Debug)]
+struct Token<'a> {
+    text: &'a str,
+    start: usize,
+}
+
+impl<'a> Token<'a> {
+    fn span(&self) -> Range<usize> {
+        self.start..(self.start + self.text.len())
+    }
+
+    fn slice(&self, range: Range<usize>) -> Self {
+        Self {
+            text: &self.text[range.clone()],
+            start: self.start + range.start,
+        }
+    }
+
+    fn trim(&self) -> Self {
+        let trimmed_start = self.text.trim_start();
+        let start = self.text.len() - trimmed_start.len();
+        self.slice(start..(start + trimmed_start.trim_end().len()))
+    }
+
+    fn split(&self, separator: char) -> Vec<Self> {
+        let mut res = vec![];
+        let mut start = 0;
+        for (i, _) in self.text.match_indices(separator) {
+            res.push(self.slice(start..i));
+            start = i + separator.len_utf8();
+        }
+        res.push(self.slice(start..self.text.len()));
+        res
+    }
+
+    fn split_once(&self, separator: char) -> Option<(Self, Self)> {
+        let i = self.text.find(separator)?;
+        Some((
+            self.slice(0..i),
+            self.slice((i + separator.len_utf8())..self.text.len()),
+        ))
+    }
+
+    fn rsplit_once(&self, separator: char) -> Option<(Self, Self)> {
+        let i = self.text.rfind(separator)?;
+        Some((
+            self.slice(0..i),
+            self.slice((i + separator.len_utf8())..self.text.len()),
+        ))
+    }
+}
+
 impl Compiler {
     pub fn build() -> Self {
-        let mut instructions = HashMap::with_capacity(256);
-        for (i, instruction_info) in INSTRUCTION_SET.iter().enumerate() {
-            instructions.insert(instruction_info.name, i as u8);
+        Self::with_instructions(Rc::default())
+    }
+
+    pub fn with_instructions(instructions: Rc<InstructionRegistry>) -> Self {
+        let mut instruction_codes = HashMap::with_capacity(256);
+        for (opcode, instruction_info) in instructions.iter() {
+            instruction_codes
+                .entry(instruction_info.name)
+                .or_insert_with(Vec::new)
+                .push(opcode);
         }
+        let mut instruction_names: Vec<_> = instruction_codes.keys().copied().collect();
+        instruction_names.sort();
         Self {
-            instruction_codes: instructions,
-            program: [0; MAX_PROGRAM_SIZE],
+            instructions,
+            instruction_codes,
+            instruction_names: Rc::new(instruction_names),
+            program: vec![0; MachineConfig::default().memory_size],
+            code_size: 0,
             label_mentions_in_program: vec![],
             line_addresses: vec![],
             line_i: 0,
-            errors: vec![],
+            errors: Rc::new(vec![]),
+            compiled_code: None,
+            case_sensitive_labels: false,
+            machine_config: MachineConfig::default(),
         }
     }
 
+    // Returns the 4-bit code of the operand, its mode and its word, if it has one.
     fn process_operand(
         operand: InstructionOperand,
         accepted_mask: u8,
         line_i: usize,
-    ) -> CompilationResult<(u8, Option<u16>)> {
+    ) -> CompilationResult<(u8, u8, Option<u16>)> {
         type Operand = InstructionOperand;
-        let mut operand_byte = 0;
-        let mut number = None;
-        match operand {
-            Operand::Reg(r) if (accepted_mask & REG_MASK) != 0 => operand_byte |= r,
-            Operand::Addr(r) if (accepted_mask & ADDR_MASK) != 0 => operand_byte |= r + 5,
-            Operand::AddrInc(r) if (accepted_mask & ADDR_INC_MASK) != 0 => operand_byte |= r + 10,
-            Operand::Port(r) if (accepted_mask & PORT_MASK) != 0 => operand_byte |= r,
-            Operand::Number(n) if (accepted_mask & NUMBER_MASK) != 0 => {
-                number = Some(n);
-                operand_byte |= NUMBER_OPERAND_CODE;
+        let is_accepted = |mask: u8| (accepted_mask & mask) != 0;
+        Ok(match operand {
+            Operand::Reg(r) if is_accepted(REG_MASK) => (r, PLAIN_MODE, None),
+            Operand::Addr(r) if is_accepted(ADDR_MASK) => (r + 5, PLAIN_MODE, None),
+            Operand::AddrInc(r) if is_accepted(ADDR_INC_MASK) => (r + 10, PLAIN_MODE, None),
+            Operand::AddrDec(r) if is_accepted(ADDR_DEC_MASK) => (r, ADDR_DEC_MODE, None),
+            Operand::Indexed(r, offset) if is_accepted(INDEXED_MASK) => {
+                (r, INDEXED_MODE, Some(offset))
+            }
+            Operand::Absolute(addr) if is_accepted(ABSOLUTE_MASK) => (0, ABSOLUTE_MODE, Some(addr)),
+            Operand::Port(p) if is_accepted(PORT_MASK) => (p & 0xF, p & HIGH_PORT_MODES, None),
+            Operand::Number(n) if is_accepted(NUMBER_MASK) => {
+                (NUMBER_OPERAND_CODE, PLAIN_MODE, Some(n))
             }
             _ => {
                 return Err(CompilationError::WrongOperandType {
@@ -152,60 +301,101 @@ impl Compiler {
                     found: operand.to_string(),
                 })
             }
-        }
-        Ok((operand_byte, number))
+        })
     }
 
+    // Encodes an instruction with already parsed operands. Their count must match `accepted`.
     fn convert_operands_to_binary(
         &self,
-        operands: InstructionOperands,
+        code: u8,
+        operands: &[(Range<usize>, InstructionOperand)],
         accepted: AcceptedOperandTypes,
-    ) -> CompilationResult<(u8, Option<u16>)> {
-        let expected_operands_num = accepted.count();
-        match operands {
-            InstructionOperands::Two(a, b) => {
-                if expected_operands_num != 2 {
-                    return Err(CompilationError::WrongNumberOfOperands {
-                        line: self.line_i,
-                        expected: expected_operands_num,
-                        found: 2,
-                    });
-                }
-                let (operand1, mut number) = Self::process_operand(a, accepted.0, self.line_i)?;
-                let (operand2, number1) = Self::process_operand(b, accepted.1, self.line_i)?;
-                if number1.is_some() {
-                    assert_eq!(number, None);
-                    number = number1;
-                }
-                let operands = (operand1 << 4) | operand2;
-                Ok((operands, number))
-            }
-            InstructionOperands::One(a) => {
-                if expected_operands_num != 1 {
-                    return Err(CompilationError::WrongNumberOfOperands {
-                        line: self.line_i,
-                        expected: expected_operands_num,
-                        found: 1,
-                    });
+    ) -> Result<Vec<u8>, ErrorsHighlightInfo> {
+        assert_eq!(operands.len(), accepted.count());
+        let mut operands_byte = 0;
+        let mut modes = [PLAIN_MODE; 2];
+        let mut words = vec![];
+        let mut errors = vec![];
+        for (i, ((span, operand), mask)) in
+            operands.iter().zip([accepted.0, accepted.1]).enumerate()
+        {
+            match Self::process_operand(*operand, mask, self.line_i) {
+                Ok((operand_code, mode, word)) => {
+                    operands_byte |= operand_code << (4 * (1 - i));
+                    modes[i] = mode;
+                    words.extend(word);
                 }
-                let (operand, number) = Self::process_operand(a, accepted.0, self.line_i)?;
-                Ok((operand << 4, number))
+                Err(e) => errors.push((span.clone(), e)),
             }
-            InstructionOperands::Zero => {
-                if expected_operands_num != 0 {
-                    return Err(CompilationError::WrongNumberOfOperands {
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        let mut binary = vec![code, operands_byte];
+        if modes != [PLAIN_MODE; 2] {
+            binary[0] |= EXTENDED_OPERANDS_FLAG;
+            binary.extend(modes);
+        }
+        for word in words {
+            binary.extend(word.to_be_bytes());
+        }
+        Ok(binary)
+    }
+
+    // Errors for operands that no variant of an instruction accepts.
+    // Expected operand types are merged from all the variants.
+    fn get_operand_errors(
+        &self,
+        operands: &[(Range<usize>, InstructionOperand)],
+        codes: &[u8],
+    ) -> ErrorsHighlightInfo {
+        let merged = codes
+            .iter()
+            .fold(AcceptedOperandTypes(0, 0), |merged, &code| {
+                let accepted = self.instruction(code).accepted_operands;
+                AcceptedOperandTypes(merged.0 | accepted.0, merged.1 | accepted.1)
+            });
+        let first = self.instruction(codes[0]).accepted_operands;
+        // Each operand may suit some variant while their combination suits none.
+        self.convert_operands_to_binary(codes[0], operands, merged)
+            .err()
+            .or_else(|| {
+                self.convert_operands_to_binary(codes[0], operands, first)
+                    .err()
+            })
+            .unwrap_or_default()
+    }
+
+    // The first operand must be followed by another general register of this machine.
+    fn get_register_pair_error(
+        &self,
+        (span, operand): &(Range<usize>, InstructionOperand),
+    ) -> Option<(Range<usize>, CompilationError)> {
+        match *operand {
+            InstructionOperand::Reg(reg) if !self.machine_config.has_register_pair(reg) => {
+                let pairs: Vec<String> = (0..self.machine_config.general_registers - 1)
+                    .map(|i| format!("R{i}"))
+                    .collect();
+                let expected = if pairs.is_empty() {
+                    "a pair of general registers".to_string()
+                } else {
+                    pairs.join(", ")
+                };
+                Some((
+                    span.clone(),
+                    CompilationError::WrongOperandType {
                         line: self.line_i,
-                        expected: expected_operands_num,
-                        found: 0,
-                    });
-                }
-                Ok((0, None))
+                        expected,
+                        found: operand.to_string(),
+                    },
+                ))
             }
+            _ => None,
         }
     }
 
     fn str_reg_to_num(r: &str) -> u8 {
-        match r {
+        match r.to_ascii_lowercase().as_str() {
             "r0" => 0,
             "r1" => 1,
             "r2" => 2,
@@ -215,115 +405,302 @@ impl Compiler {
         }
     }
 
-    fn parse_operand(&mut self, string: &str) -> CompilationResult<InstructionOperand> {
-        let string = string.trim();
+    // Parses an operand. Operands with a label get `0` in place of the label's address,
+    // and the label's name is returned to patch the address in later.
+    // The label is returned as the `@name` part of the operand, so that errors point at it.
+    fn parse_operand<'a>(
+        &self,
+        operand: Token<'a>,
+    ) -> CompilationResult<(InstructionOperand, Option<Token<'a>>)> {
+        // Registers, ports and numbers are case-insensitive, labels keep their original case.
+        let lowercase = operand.text.to_ascii_lowercase();
+        let string = lowercase.as_str();
         // Register
         if let Some((_, r)) = regex_captures!(r"^(r0|r1|r2|r3|sp)$", string) {
-            return Ok(InstructionOperand::Reg(Self::str_reg_to_num(r)));
+            return Ok((InstructionOperand::Reg(Self::str_reg_to_num(r)), None));
         }
         // Address in register
         if let Some((_, r)) = regex_captures!(r"^\((r0|r1|r2|r3|sp)\)$", string) {
-            return Ok(InstructionOperand::Addr(Self::str_reg_to_num(r)));
+            return Ok((InstructionOperand::Addr(Self::str_reg_to_num(r)), None));
         }
         // Address in register with increment
         if let Some((_, r)) = regex_captures!(r"^\((r0|r1|r2|r3|sp)\)\+$", string) {
-            return Ok(InstructionOperand::AddrInc(Self::str_reg_to_num(r)));
+            return Ok((InstructionOperand::AddrInc(Self::str_reg_to_num(r)), None));
+        }
+        // Address in register with decrement
+        if let Some((_, r)) = regex_captures!(r"^-\((r0|r1|r2|r3|sp)\)$", string) {
+            return Ok((InstructionOperand::AddrDec(Self::str_reg_to_num(r)), None));
         }
         // Port
-        if let Some((_, r)) = regex_captures!(r"^p([0-9]|1[0-5])$", string) {
-            return Ok(InstructionOperand::Port(r.parse::<u8>().unwrap()));
+        if let Some((_, p)) = regex_captures!(r"^p([0-9]{1,3})$", string) {
+            if let Ok(p) = p.parse::<u8>() {
+                return Ok((InstructionOperand::Port(p), None));
+            }
         }
         // Number
         if let Some(num) = wrapping_parse(string) {
-            return Ok(InstructionOperand::Number(num));
+            return Ok((InstructionOperand::Number(num), None));
+        }
+        // Address in register with an offset
+        if let Some((_, offset, r)) = regex_captures!(r"^(.+)\((r0|r1|r2|r3|sp)\)$", string) {
+            if let Some(offset) = wrapping_parse(offset) {
+                let r = Self::str_reg_to_num(r);
+                return Ok((InstructionOperand::Indexed(r, offset), None));
+            }
+        }
+        // Absolute address
+        if let Some((_, addr)) = regex_captures!(r"^\((.+)\)$", string) {
+            if let Some(addr) = wrapping_parse(addr) {
+                return Ok((InstructionOperand::Absolute(addr), None));
+            }
         }
         // Label
-        if let Some((_, label_name)) = regex_captures!(r"^@(\w+)$", string) {
-            self.label_mentions_in_program.push((
-                label_name.to_string(),
-                (self.line_i, self.line_addresses.last().unwrap() + 2),
+        if regex_is_match!(r"^@\w+$", operand.text) {
+            return Ok((InstructionOperand::Number(0), Some(operand)));
+        }
+        // Address in register with a label as the offset
+        if let Some((_, label_name, r)) =
+            regex_captures!(r"^@(\w+)\(((?i:r0|r1|r2|r3|sp))\)$", operand.text)
+        {
+            let r = Self::str_reg_to_num(r);
+            return Ok((
+                InstructionOperand::Indexed(r, 0),
+                Some(operand.slice(0..(label_name.len() + 1))),
+            ));
+        }
+        // Label as an absolute address
+        if let Some((_, label_name)) = regex_captures!(r"^\(@(\w+)\)$", operand.text) {
+            return Ok((
+                InstructionOperand::Absolute(0),
+                Some(operand.slice(1..(label_name.len() + 2))),
             ));
-            return Ok(InstructionOperand::Number(0));
         }
-        Err(CompilationError::InvalidOperand {
+        Err(self.invalid_operand(operand))
+    }
+
+    // Every correctly spelled operand that isn't a number or a label.
+    fn operand_names(&self) -> Vec<String> {
+        let mut res = vec![];
+        for r in self.machine_config.register_names() {
+            res.push(format!("({r})"));
+            res.push(format!("({r})+"));
+            res.push(format!("-({r})"));
+            res.push(r);
+        }
+        for p in 0..self.machine_config.ports() {
+            res.push(format!("p{p}"));
+        }
+        res
+    }
+
+    fn invalid_operand(&self, operand: Token) -> CompilationError {
+        let names = self.operand_names();
+        CompilationError::InvalidOperand {
             line: self.line_i,
-            operand: string.to_string(),
-        })
+            operand: operand.text.to_string(),
+            suggestion: closest_match(
+                &operand.text.to_ascii_lowercase(),
+                names.iter().map(String::as_str),
+            )
+            .map(str::to_string),
+        }
     }
 
     // Compiles a single assembly instruction and returns its binary code
-    fn process_instruction(&mut self, text: &str) -> CompilationResult<Option<(u16, Option<u16>)>> {
-        let words = text.splitn(2, ' ').collect::<Vec<&str>>();
-        if words.is_empty() {
-            return Ok(None);
-        }
-        let name = words[0];
-        if name.is_empty() {
+    // together with the labels it mentions.
+    // All problems found in the instruction are reported, each with the span of its own token.
+    fn process_instruction(
+        &mut self,
+        text: Token,
+    ) -> Result<Option<AssembledInstruction>, ErrorsHighlightInfo> {
+        let (name, operands_text) = match text.split_once(' ') {
+            Some((name, operands)) => (name, Some(operands.trim())),
+            None => (text, None),
+        };
+        if name.text.is_empty() {
             return Ok(None);
         }
-        let code =
-            *self
-                .instruction_codes
-                .get(name)
-                .ok_or(CompilationError::UnknownInstruction {
+        let mut errors = vec![];
+        let codes = self
+            .instruction_codes
+            .get(name.text.to_ascii_lowercase().as_str())
+            .cloned();
+        if codes.is_none() {
+            errors.push((
+                name.span(),
+                CompilationError::UnknownInstruction {
                     line: self.line_i,
-                    instruction: name.to_string(),
-                })?;
-        let info = &INSTRUCTION_SET[code as usize];
-        let operands = if let Some(operands) = words.get(1) {
-            operands.split(',').collect::<Vec<&str>>()
-        } else {
-            vec![]
-        };
-        if operands.len() != info.accepted_operands.count() {
-            return Err(CompilationError::WrongNumberOfOperands {
-                line: self.line_i,
-                expected: info.accepted_operands.count(),
-                found: operands.len(),
-            });
+                    instruction: name.text.to_string(),
+                    suggestion: closest_match(
+                        &name.text.to_ascii_lowercase(),
+                        self.instructions.iter().map(|(_, info)| info.name),
+                    )
+                    .map(str::to_string),
+                },
+            ));
         }
-        let operands = match operands[..] {
-            [] => InstructionOperands::Zero,
-            [a] => InstructionOperands::One(self.parse_operand(a)?),
-            [a, b] => InstructionOperands::Two(self.parse_operand(a)?, self.parse_operand(b)?),
-            _ => unreachable!(),
+        let operand_tokens = match operands_text {
+            Some(operands) => operands.split(',').iter().map(Token::trim).collect(),
+            None => vec![],
         };
-        let (operands, number) =
-            self.convert_operands_to_binary(operands, info.accepted_operands)?;
-        Ok(Some((((code as u16) << 8) | operands as u16, number)))
+        let mut operands = vec![];
+        let mut labels = vec![];
+        for operand in operand_tokens.iter().copied() {
+            match self.parse_operand(operand) {
+                // E.g. `r3` on a machine with 2 general registers.
+                Ok((parsed, _)) if !self.machine_config.has_operand(parsed) => {
+                    errors.push((operand.span(), self.invalid_operand(operand)))
+                }
+                Ok((parsed, label)) => {
+                    operands.push((operand.span(), parsed));
+                    labels.push(label);
+                }
+                Err(e) => errors.push((operand.span(), e)),
+            }
+        }
+        if let Some(codes) = codes {
+            let info = self.instruction(codes[0]);
+            if operand_tokens.len() != info.accepted_operands.count() {
+                errors.push((
+                    operands_text.unwrap_or(name).span(),
+                    CompilationError::WrongNumberOfOperands {
+                        line: self.line_i,
+                        expected: info.accepted_operands.count(),
+                        found: operand_tokens.len(),
+                    },
+                ));
+            } else if operands.len() == operand_tokens.len() {
+                if info.requires_register_pair {
+                    errors.extend(self.get_register_pair_error(&operands[0]));
+                }
+                // The first variant of the instruction that accepts the operands is used.
+                let binary = codes.iter().find_map(|&code| {
+                    let accepted = self.instruction(code).accepted_operands;
+                    self.convert_operands_to_binary(code, &operands, accepted)
+                        .ok()
+                });
+                match binary {
+                    Some(binary) if errors.is_empty() => {
+                        // Operand words follow the instruction header in the order of operands.
+                        let mut word_offset = if binary[0] & EXTENDED_OPERANDS_FLAG != 0 {
+                            4
+                        } else {
+                            2
+                        };
+                        let mut label_mentions = vec![];
+                        for ((_, operand), label) in operands.into_iter().zip(labels) {
+                            if !operand.has_word() {
+                                continue;
+                            }
+                            if let Some(label) = label {
+                                let name = label.text[1..].to_string();
+                                label_mentions.push((name, label.span(), word_offset));
+                            }
+                            word_offset += 2;
+                        }
+                        return Ok(Some((binary, label_mentions)));
+                    }
+                    Some(_) => {}
+                    None => errors.extend(self.get_operand_errors(&operands, &codes)),
+                }
+            }
+        }
+        Err(errors)
     }
 
-    fn preprocess_line(line: &str) -> &str {
-        line.trim().split(';').next().unwrap()
+    fn preprocess_line(line: Token) -> Token {
+        line.split(';')[0].trim()
+    }
+
+    // Splits a preprocessed line into the labels defined on it and the remaining instruction.
+    // Every `:` on a line ends a label, e.g. `a: b: add r0, 1` defines `a` and `b`.
+    fn split_labels(line: Token) -> (Vec<Token>, Token) {
+        match line.rsplit_once(':') {
+            Some((labels, instruction)) => (
+                labels.split(':').iter().map(Token::trim).collect(),
+                instruction.trim(),
+            ),
+            None => (vec![], line),
+        }
+    }
+
+    // Labels that differ only in case are the same label unless `case_sensitive_labels` is set.
+    fn label_key(&self, name: &str) -> String {
+        if self.case_sensitive_labels {
+            name.to_string()
+        } else {
+            name.to_lowercase()
+        }
+    }
+
+    pub fn case_sensitive_labels(&self) -> bool {
+        self.case_sensitive_labels
+    }
+
+    pub fn set_case_sensitive_labels(&mut self, case_sensitive: bool) {
+        if self.case_sensitive_labels != case_sensitive {
+            self.case_sensitive_labels = case_sensitive;
+            // The cached program may resolve labels differently now.
+            self.compiled_code = None;
+        }
+    }
+
+    pub fn instructions(&self) -> &InstructionRegistry {
+        &self.instructions
+    }
+
+    pub fn instruction_names(&self) -> Rc<Vec<&'static str>> {
+        self.instruction_names.clone()
+    }
+
+    // `code` is one of `instruction_codes`.
+    fn instruction(&self, code: u8) -> &InstructionInfo {
+        self.instructions.get(code).unwrap()
+    }
+
+    pub fn machine_config(&self) -> MachineConfig {
+        self.machine_config
+    }
+
+    pub fn set_machine_config(&mut self, config: MachineConfig) {
+        if self.machine_config != config {
+            self.machine_config = config;
+            self.compiled_code = None;
+        }
     }
 
     pub fn compile_code(&mut self, asm_code: &str) {
-        self.program = [0; MAX_PROGRAM_SIZE];
-        let asm_code = asm_code.to_lowercase();
-        let lines: Vec<(usize, &str)> = asm_code.split('\n').enumerate().collect();
+        self.program = vec![0; self.machine_config.memory_size];
+        let lines: Vec<(usize, Token)> = Token {
+            text: asm_code,
+            start: 0,
+        }
+        .split('\n')
+        .into_iter()
+        .enumerate()
+        .collect();
         let mut label_names = HashSet::new();
         let mut errors = vec![];
 
         // Saving names of created labels.
-        let mut curr_symbol = 0;
-        for &(i, raw_line) in &lines {
-            let raw_line_len = raw_line.chars().count() + 1;
-            let line = Self::preprocess_line(raw_line);
-            if let Some(label_name) = line.strip_suffix(':') {
+        for &(i, line) in &lines {
+            for label in Self::split_labels(Self::preprocess_line(line)).0 {
+                let label_name = label.text;
                 if regex_is_match!(r"^(?:\w)+$", label_name) {
-                    if label_names.contains(label_name) {
+                    let key = self.label_key(label_name);
+                    if label_names.contains(&key) {
                         errors.push((
-                            curr_symbol..(curr_symbol + raw_line_len),
+                            label.span(),
                             CompilationError::LabelAlreadyExists {
                                 line: i,
                                 name: label_name.to_string(),
                             },
                         ));
                     }
-                    label_names.insert(label_name);
+                    label_names.insert(key);
                 } else {
                     errors.push((
-                        curr_symbol..(curr_symbol + raw_line_len),
+                        label.span(),
                         CompilationError::InvalidLabelName {
                             line: i,
                             name: label_name.to_string(),
@@ -331,76 +708,113 @@ impl Compiler {
                     ));
                 }
             }
-            curr_symbol += raw_line_len;
         }
         // Compiling the program
-        let mut curr_symbol = 0;
         let mut label_addresses = HashMap::new();
-        let mut line_start_symbol_indexes = vec![];
         self.label_mentions_in_program.clear();
         self.line_addresses = vec![0];
         for &(i, line) in &lines {
-            line_start_symbol_indexes.push(curr_symbol);
             self.line_i = i;
             let mut instruction_size = 0;
-            let line_len_raw = line.len() + 1;
-            let line = Self::preprocess_line(line);
-            if line.ends_with(':') {
-                // Label
-                let label_name = &line[..(line.len() - 1)];
+            let (line_labels, instruction) = Self::split_labels(Self::preprocess_line(line));
+            // Labels
+            for label in line_labels {
                 if let Some(&addr) = self.line_addresses.last() {
-                    label_addresses.insert(label_name, addr);
+                    label_addresses.insert(self.label_key(label.text), (label.text, addr));
                 }
-            } else {
-                // Instruction
-                match self.process_instruction(line) {
-                    Ok(binary) => {
-                        let addr = *self.line_addresses.last().unwrap();
-                        if let Some((instruction, number)) = binary {
-                            self.program[addr] = (instruction >> 8) as u8;
-                            self.program[addr + 1] = instruction as u8;
-                            match number {
-                                Some(number) => {
-                                    if addr + 1 >= self.program.len() {
-                                        errors.push((
-                                            curr_symbol..(curr_symbol + line_len_raw),
-                                            CompilationError::OutOfMemory { line: i },
-                                        ));
-                                    }
-                                    self.program[addr + 2] = (number >> 8) as u8;
-                                    self.program[addr + 3] = number as u8;
-                                    instruction_size = 4;
-                                }
-                                None => instruction_size = 2,
-                            }
+            }
+            // Instruction
+            match self.process_instruction(instruction) {
+                Ok(Some((binary, label_mentions))) => {
+                    let addr = *self.line_addresses.last().unwrap();
+                    if addr + binary.len() > self.program.len() {
+                        errors.push((
+                            instruction.span(),
+                            CompilationError::OutOfMemory { line: i },
+                        ));
+                    } else {
+                        self.program[addr..(addr + binary.len())].copy_from_slice(&binary);
+                        instruction_size = binary.len();
+                        for (label, span, offset) in label_mentions {
+                            self.label_mentions_in_program
+                                .push((label, span, (i, addr + offset)));
                         }
                     }
-                    Err(e) => errors.push((curr_symbol..(curr_symbol + line_len_raw), e)),
                 }
+                Ok(None) => {}
+                Err(instruction_errors) => errors.extend(instruction_errors),
             }
             self.line_addresses
                 .push(self.line_addresses.last().unwrap() + instruction_size);
-            curr_symbol += line_len_raw;
         }
-        line_start_symbol_indexes.push(asm_code.chars().count());
         // Replacing currently uninitialized label @mentions in code with the right addresses.
-        for (label, (label_line, mention_addr)) in self.label_mentions_in_program.clone() {
-            if let Some(&addr) = label_addresses.get(label.as_str()) {
+        for (label, span, (label_line, mention_addr)) in self.label_mentions_in_program.clone() {
+            let key = self.label_key(&label);
+            if let Some(&(_, addr)) = label_addresses.get(&key) {
                 assert_eq!(self.program[mention_addr], 0);
                 assert_eq!(self.program[mention_addr + 1], 0);
                 self.program[mention_addr] = (addr >> 8) as u8;
                 self.program[mention_addr + 1] = addr as u8;
             } else {
                 errors.push((
-                    line_start_symbol_indexes[label_line]
-                        ..line_start_symbol_indexes[label_line + 1],
+                    span,
                     CompilationError::NoLabelWithSuchName {
                         line: label_line,
+                        suggestion: closest_match(&key, label_addresses.keys().map(String::as_str))
+                            .map(|key| format!("@{}", label_addresses[key].0)),
                         name: label,
                     },
                 ));
             }
         }
-        self.errors = errors;
+        self.code_size = *self.line_addresses.last().unwrap();
+        self.errors = Rc::new(errors);
+        self.compiled_code = Some(asm_code.to_string());
+    }
+
+    /// Compiles the code only if it differs from the last compiled one.
+    ///
+    /// Cheap to call every frame: when nothing changed, the cached program and errors are kept.
+    pub fn compile_if_changed(&mut self, asm_code: &str) {
+        if self.compiled_code.as_deref() == Some(asm_code) {
+            return;
+        }
+        self.compile_code(asm_code);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Line and columns of every error, in the order they are reported.
+    fn error_positions(code: &str) -> Vec<(usize, Range<usize>)> {
+        let mut compiler = Compiler::build();
+        compiler.compile_code(code);
+        compiler
+            .errors
+            .iter()
+            .map(|(span, error)| {
+                let line_start = code[..span.start].rfind('\n').map_or(0, |i| i + 1);
+                let line = code[..span.start].matches('\n').count();
+                // The message and the underline agree on the line.
+                assert!(error
+                    .to_string()
+                    .starts_with(&format!("line {}:", line + 1)));
+                (line, (span.start - line_start)..(span.end - line_start))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn every_bad_operand_is_reported() {
+        let code = "mov r0, 1\nadd rr, (r9)\nstop\n";
+        assert_eq!(error_positions(code), [(1, 4..6), (1, 8..12)]);
+    }
+
+    #[test]
+    fn bad_label_and_instruction_on_one_line() {
+        let code = "mov r0, 1\na-b: mvo r0, 2\nstop\n";
+        assert_eq!(error_positions(code), [(1, 0..3), (1, 5..8)]);
     }
 }
@@ -0,0 +1,161 @@
+// Devices attached to the ports. Output goes to the lamp panel (`ProgramExecutor::display`),
+// input comes from the devices below.
+
+use std::collections::VecDeque;
+
+// Rows of 16 switches toggled by the user, one row per port.
+#[derive(Clone)]
+pub struct SwitchPanel {
+    switches: Vec<u16>,
+    // Toggles so far, loop detection compares them instead of all the rows.
+    changes: u64,
+}
+
+impl SwitchPanel {
+    pub fn new(ports: usize) -> Self {
+        Self::from_rows(vec![0; ports])
+    }
+
+    pub fn read(&self, port: u8) -> u16 {
+        self.switches[port as usize]
+    }
+
+    pub fn is_on(&self, port: usize, bit: usize) -> bool {
+        (self.switches[port] >> bit) & 1 == 1
+    }
+
+    pub fn toggle(&mut self, port: usize, bit: usize) {
+        self.switches[port] ^= 1 << bit;
+        self.changes += 1;
+    }
+
+    pub fn changes(&self) -> u64 {
+        self.changes
+    }
+
+    pub fn rows(&self) -> &[u16] {
+        &self.switches
+    }
+
+    pub fn from_rows(switches: Vec<u16>) -> Self {
+        Self {
+            switches,
+            changes: 0,
+        }
+    }
+}
+
+// Ports of the keyboard. They take the place of two rows of switches.
+pub const KEY_STATE_PORT: u8 = 14;
+pub const KEY_CODE_PORT: u8 = 15;
+const KEY_QUEUE_CAPACITY: usize = 16;
+
+// Keys with a bit in `KEY_STATE_PORT`, the bit number is the position in this list.
+const STATE_KEYS: [u16; 7] = [
+    KEY_LEFT, KEY_UP, KEY_RIGHT, KEY_DOWN, KEY_SPACE, KEY_ENTER, KEY_ESCAPE,
+];
+
+// Key codes. Letters and digits use their ASCII codes, letters in upper case.
+pub const KEY_BACKSPACE: u16 = 0x08;
+pub const KEY_TAB: u16 = 0x09;
+pub const KEY_ENTER: u16 = 0x0D;
+pub const KEY_ESCAPE: u16 = 0x1B;
+pub const KEY_SPACE: u16 = 0x20;
+pub const KEY_LEFT: u16 = 0x80;
+pub const KEY_UP: u16 = 0x81;
+pub const KEY_RIGHT: u16 = 0x82;
+pub const KEY_DOWN: u16 = 0x83;
+
+// Code of a key by its name, e.g. `A`, `7`, `Space` or `ArrowLeft`.
+// Names match `egui::Key::name`, so key scripts and the GUI use the same ones.
+pub fn key_code(name: &str) -> Option<u16> {
+    if let [c] = name.as_bytes() {
+        if c.is_ascii_alphanumeric() {
+            return Some(c.to_ascii_uppercase() as u16);
+        }
+    }
+    Some(match name.to_ascii_lowercase().as_str() {
+        "backspace" => KEY_BACKSPACE,
+        "tab" => KEY_TAB,
+        "enter" => KEY_ENTER,
+        "escape" => KEY_ESCAPE,
+        "space" => KEY_SPACE,
+        "arrowleft" => KEY_LEFT,
+        "arrowup" => KEY_UP,
+        "arrowright" => KEY_RIGHT,
+        "arrowdown" => KEY_DOWN,
+        _ => return None,
+    })
+}
+
+// Reading `KEY_STATE_PORT` gives the keys that are held down, see `STATE_KEYS`.
+// Reading `KEY_CODE_PORT` takes the oldest pressed key from the queue, or 0 if it's empty.
+// Key presses don't raise interrupts, the machine has none yet, so programs poll the ports.
+#[derive(Clone, Default)]
+pub struct Keyboard {
+    state: u16,
+    queue: VecDeque<u16>,
+    // Presses, releases and reads of a queued key so far, like `SwitchPanel::changes`.
+    changes: u64,
+}
+
+impl Keyboard {
+    pub fn read(&mut self, port: u8) -> Option<u16> {
+        let value = self.peek(port);
+        if port == KEY_CODE_PORT && self.queue.pop_front().is_some() {
+            self.changes += 1;
+        }
+        value
+    }
+
+    // Like `read`, but leaves the queue as it is.
+    pub fn peek(&self, port: u8) -> Option<u16> {
+        match port {
+            KEY_STATE_PORT => Some(self.state),
+            KEY_CODE_PORT => Some(self.queue.front().copied().unwrap_or(0)),
+            _ => None,
+        }
+    }
+
+    // Keys pressed while the queue is full are lost.
+    pub fn press(&mut self, code: u16) {
+        self.state |= Self::state_bit(code);
+        if self.queue.len() < KEY_QUEUE_CAPACITY {
+            self.queue.push_back(code);
+        }
+        self.changes += 1;
+    }
+
+    pub fn release(&mut self, code: u16) {
+        self.state &= !Self::state_bit(code);
+        self.changes += 1;
+    }
+
+    pub fn changes(&self) -> u64 {
+        self.changes
+    }
+
+    pub fn held_keys(&self) -> u16 {
+        self.state
+    }
+
+    pub fn queued_keys(&self) -> Vec<u16> {
+        self.queue.iter().copied().collect()
+    }
+
+    // Keys that don't fit into the queue are dropped.
+    pub fn from_parts(state: u16, queue: &[u16]) -> Self {
+        Self {
+            state,
+            queue: queue.iter().copied().take(KEY_QUEUE_CAPACITY).collect(),
+            changes: 0,
+        }
+    }
+
+    fn state_bit(code: u16) -> u16 {
+        STATE_KEYS
+            .iter()
+            .position(|&key| key == code)
+            .map_or(0, |i| 1 << i)
+    }
+}
@@ -1,46 +1,287 @@
-use crate::compiler::MAX_PROGRAM_SIZE;
+use crate::breakpoints::Breakpoint;
+use crate::devices::{Keyboard, SwitchPanel};
+use crate::instruction_registry::InstructionRegistry;
 use crate::instruction_set::{
-    AcceptedOperandTypes, InstructionInfo, InstructionOperand, InstructionOperands, ADDR_INC_MASK,
-    ADDR_MASK, INSTRUCTION_SET, NUMBER_MASK, NUMBER_OPERAND_CODE, PORT_MASK, REG_MASK,
+    AcceptedOperandTypes, InstructionInfo, InstructionOperand, InstructionOperands, ABSOLUTE_MASK,
+    ABSOLUTE_MODE, ADDR_DEC_MASK, ADDR_DEC_MODE, ADDR_INC_MASK, ADDR_MASK, EXTENDED_OPERANDS_FLAG,
+    HIGH_PORT_MODES, INDEXED_MASK, INDEXED_MODE, NUMBER_MASK, NUMBER_OPERAND_CODE, PLAIN_MODE,
+    PORT_MASK, REG_MASK,
 };
+use crate::loop_detection::{LoopDetector, MachineState, MAX_ZERO_INSTRUCTIONS_IN_A_ROW};
+use crate::machine::{MachineConfig, MAX_MEMORY_SIZE};
+use crate::snapshot::Snapshot;
+use crate::trace::{disassemble, Tracer};
+use crate::watchpoints::{WatchKind, WatchTarget, Watchpoint, WatchpointHit};
+use std::collections::BTreeSet;
 use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+
+// What happens when a program writes to its own instructions.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CodeProtection {
+    Off,
+    // The write fails with `RuntimeError::WriteToCode`.
+    ReadOnly,
+    // The write succeeds, but is logged and highlighted.
+    Log,
+}
+
+// A write to the code: `address` was written by the instruction at `err_address`.
+#[derive(Clone, Copy, Debug)]
+pub struct CodeWrite {
+    pub err_address: usize,
+    pub address: usize,
+}
+
+// Memory reserved for the stack: `bottom..top`. SP starts at `top` and `-(sp)` moves it down,
+// so accesses through SP below `bottom` overflow the stack and at `top` or above underflow it.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct StackRegion {
+    pub bottom: usize,
+    pub top: usize,
+}
+
+impl StackRegion {
+    // Where SP starts, it must fit in a word. On a 64 KiB machine
+    // the last word of memory is left out of the stack.
+    pub fn top(memory_size: usize) -> usize {
+        memory_size.min(MAX_MEMORY_SIZE - 2)
+    }
+
+    // The top `size` bytes of memory, `size` is at most `top(memory_size)`.
+    pub fn at_the_end_of_memory(size: usize, memory_size: usize) -> Self {
+        let top = Self::top(memory_size);
+        Self {
+            bottom: top - size,
+            top,
+        }
+    }
+
+    pub fn contains(&self, addr: usize) -> bool {
+        (self.bottom..self.top).contains(&addr)
+    }
+}
+
+// Only the first writes are logged, a loop may write to the code over and over.
+const MAX_LOGGED_CODE_WRITES: usize = 100;
+
+// Bits of `program_state_reg`
+pub const CARRY_FLAG: u16 = 1 << 0;
+pub const OVERFLOW_FLAG: u16 = 1 << 3;
 
 pub struct ProgramExecutor {
+    instructions: Rc<InstructionRegistry>,
+    // Changed with `set_machine_config`, as memory and ports are sized by it.
+    config: MachineConfig,
+    // R0..R3 and SP, general registers missing from `config` are never used.
     pub registers: [u16; 5],
     pub program_state_reg: u16,
-    pub memory: [u8; MAX_PROGRAM_SIZE],
-    pub display: [u16; 16],
+    pub memory: Vec<u8>,
+    // The loaded instructions take the first `code_size` bytes of memory.
+    pub code_size: usize,
+    pub code_protection: CodeProtection,
+    pub code_writes: Vec<CodeWrite>,
+    // Every code byte that was overwritten in the current run.
+    pub modified_code: BTreeSet<usize>,
+    // Accesses through SP outside of the region are runtime errors.
+    pub stack_region: Option<StackRegion>,
+    // Output ports, shown by the lamps.
+    pub display: Vec<u16>,
+    // Input ports.
+    pub switches: SwitchPanel,
+    pub keyboard: Keyboard,
     pub has_finished: bool,
     pub is_in_debug_mode: bool,
+    // Division by zero raises `RuntimeError::DivisionByZero` instead of giving 0 with overflow.
+    pub strict_division: bool,
+    // Stop with a `RuntimeError` once the program provably loops forever
+    // or runs into zeroed memory.
+    pub detect_loops: bool,
+    loop_detector: LoopDetector,
+    // Stop with a `RuntimeError` after this many instructions.
+    pub max_instructions: Option<u64>,
+    pub watchpoints: Vec<Watchpoint>,
+    // The first watchpoint triggered by the last executed instruction.
+    pub watchpoint_hit: Option<WatchpointHit>,
+    pub breakpoints: Vec<Breakpoint>,
+    // The first breakpoint at `curr_addr` whose condition held when it was reached.
+    pub breakpoint_hit: Option<usize>,
+    // Records every executed instruction when set.
+    pub tracer: Option<Tracer>,
     // pub
     pub curr_addr: usize,
+    // Counters of the current run, only successfully executed instructions are counted.
+    pub executed_instructions: u64,
+    pub cycles: u64,
 }
 
 impl Default for ProgramExecutor {
     fn default() -> Self {
+        let config = MachineConfig::default();
         Self {
-            registers: [0, 0, 0, 0, (MAX_PROGRAM_SIZE - 1) as u16],
+            instructions: Rc::default(),
+            config,
+            registers: [0, 0, 0, 0, StackRegion::top(config.memory_size) as u16],
             program_state_reg: 0,
-            memory: [0; MAX_PROGRAM_SIZE],
-            display: [0; 16],
+            memory: vec![0; config.memory_size],
+            code_size: 0,
+            code_protection: CodeProtection::Off,
+            code_writes: vec![],
+            modified_code: BTreeSet::new(),
+            stack_region: None,
+            display: vec![0; config.ports()],
+            switches: SwitchPanel::new(config.ports()),
+            keyboard: Keyboard::default(),
             has_finished: true,
             is_in_debug_mode: false,
+            strict_division: false,
+            detect_loops: true,
+            loop_detector: LoopDetector::default(),
+            max_instructions: None,
+            watchpoints: vec![],
+            watchpoint_hit: None,
+            breakpoints: vec![],
+            breakpoint_hit: None,
+            tracer: None,
             curr_addr: 0,
+            executed_instructions: 0,
+            cycles: 0,
         }
     }
 }
 
 impl ProgramExecutor {
+    fn set_flag(&mut self, flag: u16, value: bool) {
+        if value {
+            self.program_state_reg |= flag;
+        } else {
+            self.program_state_reg &= !flag;
+        }
+    }
+
     pub fn set_overflow(&mut self, overflow: bool) {
-        self.program_state_reg = (1 << 3) * (overflow as u16);
+        self.set_flag(OVERFLOW_FLAG, overflow);
+    }
+
+    // Unlike overflow, carry is changed only by instructions that use it,
+    // so it survives between e.g. `add` and `adc`.
+    pub fn set_carry(&mut self, carry: bool) {
+        self.set_flag(CARRY_FLAG, carry);
+    }
+
+    pub fn carry(&self) -> bool {
+        self.program_state_reg & CARRY_FLAG != 0
     }
 
     pub fn prepare_for_a_new_run(&mut self) {
         self.curr_addr = 0;
-        self.registers[4] = (MAX_PROGRAM_SIZE - 1) as u16;
+        self.registers[4] = self.initial_sp();
+        self.keyboard = Keyboard::default();
+        self.executed_instructions = 0;
+        self.cycles = 0;
+        self.loop_detector = LoopDetector::default();
+        self.code_writes.clear();
+        self.modified_code.clear();
+        self.breakpoint_hit = None;
+        for breakpoint in &mut self.breakpoints {
+            breakpoint.hits = 0;
+        }
+        if let Some(tracer) = &mut self.tracer {
+            tracer.clear();
+        }
         self.has_finished = false;
     }
 
+    // Without a stack region the stack starts at the same even address as with one.
+    pub fn initial_sp(&self) -> u16 {
+        self.stack_region
+            .map_or(StackRegion::top(self.config.memory_size), |region| {
+                region.top
+            }) as u16
+    }
+
+    pub fn with_instructions(instructions: Rc<InstructionRegistry>) -> Self {
+        Self {
+            instructions,
+            ..Self::default()
+        }
+    }
+
+    pub fn instructions(&self) -> &InstructionRegistry {
+        &self.instructions
+    }
+
+    pub fn config(&self) -> MachineConfig {
+        self.config
+    }
+
+    // Turns the machine off: memory, registers, lamps and devices are cleared.
+    // The stack region is moved to the end of the new memory.
+    pub fn set_machine_config(&mut self, config: MachineConfig) {
+        self.config = config;
+        self.memory = vec![0; config.memory_size];
+        self.code_size = 0;
+        self.display = vec![0; config.ports()];
+        self.switches = SwitchPanel::new(config.ports());
+        self.stack_region = self.stack_region.map(|region| {
+            let size = (region.top - region.bottom).min(StackRegion::top(config.memory_size));
+            StackRegion::at_the_end_of_memory(size, config.memory_size)
+        });
+        self.prepare_for_a_new_run();
+        self.registers = [0, 0, 0, 0, self.initial_sp()];
+        self.program_state_reg = 0;
+        self.has_finished = true;
+    }
+
+    // The program is cut to the size of memory.
+    pub fn load_program(&mut self, program: &[u8], code_size: usize) {
+        let size = program.len().min(self.memory.len());
+        self.memory.fill(0);
+        self.memory[..size].copy_from_slice(&program[..size]);
+        self.code_size = code_size.min(self.memory.len());
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            config: self.config,
+            memory: self.memory.clone(),
+            code_size: self.code_size,
+            registers: self.registers,
+            program_state_reg: self.program_state_reg,
+            curr_addr: self.curr_addr,
+            has_finished: self.has_finished,
+            display: self.display.clone(),
+            switches: self.switches.clone(),
+            keyboard: self.keyboard.clone(),
+            executed_instructions: self.executed_instructions,
+            cycles: self.cycles,
+        }
+    }
+
+    // Settings, watchpoints and breakpoints are kept, the history of the run is not.
+    // The machine takes the configuration of the snapshot.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        if self.config != snapshot.config {
+            self.set_machine_config(snapshot.config);
+        }
+        self.memory.clone_from(&snapshot.memory);
+        self.code_size = snapshot.code_size;
+        self.registers = snapshot.registers;
+        self.program_state_reg = snapshot.program_state_reg;
+        self.curr_addr = snapshot.curr_addr;
+        self.has_finished = snapshot.has_finished;
+        self.display.clone_from(&snapshot.display);
+        self.switches = snapshot.switches.clone();
+        self.keyboard = snapshot.keyboard.clone();
+        self.executed_instructions = snapshot.executed_instructions;
+        self.cycles = snapshot.cycles;
+        self.loop_detector = LoopDetector::default();
+        self.code_writes.clear();
+        self.modified_code.clear();
+        self.watchpoint_hit = None;
+        self.breakpoint_hit = None;
+    }
+
     pub fn read_u8(&self, addr: u16) -> RuntimeResult<u8> {
         let addr = addr as usize;
         Ok(*self.memory.get(addr).ok_or(RuntimeError::InvalidAddress {
@@ -50,14 +291,21 @@ impl ProgramExecutor {
     }
 
     pub fn write_u8(&mut self, addr: u16, new_val: u8) -> RuntimeResult<()> {
-        let addr = addr as usize;
-        *self
-            .memory
-            .get_mut(addr)
-            .ok_or(RuntimeError::InvalidAddress {
-                err_address: self.curr_addr,
-                address: addr,
-            })? = new_val;
+        let old_val = self.read_u8(addr)?;
+        if old_val != new_val {
+            self.loop_detector.side_effect();
+        }
+        if (addr as usize) < self.code_size {
+            self.write_to_code(addr as usize)?;
+        }
+        self.watch_write(
+            |target| target.contains_memory(addr, addr),
+            old_val != new_val,
+        );
+        if let Some(tracer) = &mut self.tracer {
+            tracer.memory_write(addr, new_val);
+        }
+        self.memory[addr as usize] = new_val;
         Ok(())
     }
 
@@ -78,26 +326,52 @@ impl ProgramExecutor {
         &self,
         accepted_operand_types: u8,
         operand: u8,
+        mode: u8,
+        word_addr: &mut u16,
     ) -> RuntimeResult<InstructionOperand> {
-        Ok(if (accepted_operand_types & PORT_MASK) != 0 {
-            InstructionOperand::Port(operand)
-        } else if (accepted_operand_types & NUMBER_MASK) != 0 && operand == NUMBER_OPERAND_CODE {
-            InstructionOperand::Number(u16::from_be_bytes([
-                self.memory[self.curr_addr + 2],
-                self.memory[self.curr_addr + 3],
-            ]))
-        } else if (accepted_operand_types & ADDR_INC_MASK) != 0 && (10..15).contains(&operand) {
-            InstructionOperand::AddrInc(operand - 10)
-        } else if (accepted_operand_types & ADDR_MASK) != 0 && (5..10).contains(&operand) {
-            InstructionOperand::Addr(operand - 5)
-        } else if (accepted_operand_types & REG_MASK) != 0 && (0..5).contains(&operand) {
-            InstructionOperand::Reg(operand)
-        } else {
-            return Err(RuntimeError::InvalidOperand {
-                err_address: self.curr_addr,
-                operand,
-            });
-        })
+        let mut next_word = || {
+            let word = self.read_u16(*word_addr)?;
+            *word_addr = word_addr.wrapping_add(2);
+            Ok(word)
+        };
+        let is_accepted = |mask: u8| (accepted_operand_types & mask) != 0;
+        let invalid_operand = RuntimeError::InvalidOperand {
+            err_address: self.curr_addr,
+            operand,
+        };
+        let result = match mode {
+            // The plain mode is zero, so it gives ports 0..=15.
+            _ if is_accepted(PORT_MASK) && mode & !HIGH_PORT_MODES == 0 => {
+                InstructionOperand::Port(mode | operand)
+            }
+            PLAIN_MODE if is_accepted(NUMBER_MASK) && operand == NUMBER_OPERAND_CODE => {
+                InstructionOperand::Number(next_word()?)
+            }
+            PLAIN_MODE if is_accepted(ADDR_INC_MASK) && (10..15).contains(&operand) => {
+                InstructionOperand::AddrInc(operand - 10)
+            }
+            PLAIN_MODE if is_accepted(ADDR_MASK) && (5..10).contains(&operand) => {
+                InstructionOperand::Addr(operand - 5)
+            }
+            PLAIN_MODE if is_accepted(REG_MASK) && (0..5).contains(&operand) => {
+                InstructionOperand::Reg(operand)
+            }
+            ADDR_DEC_MODE if is_accepted(ADDR_DEC_MASK) && (0..5).contains(&operand) => {
+                InstructionOperand::AddrDec(operand)
+            }
+            INDEXED_MODE if is_accepted(INDEXED_MASK) && (0..5).contains(&operand) => {
+                InstructionOperand::Indexed(operand, next_word()?)
+            }
+            ABSOLUTE_MODE if is_accepted(ABSOLUTE_MASK) => {
+                InstructionOperand::Absolute(next_word()?)
+            }
+            _ => return Err(invalid_operand),
+        };
+        // Registers and ports that this machine doesn't have.
+        if !self.config.has_operand(result) {
+            return Err(invalid_operand);
+        }
+        Ok(result)
     }
 
     pub fn get_instruction_operands(
@@ -105,54 +379,390 @@ impl ProgramExecutor {
         accepted_operand_types: AcceptedOperandTypes,
     ) -> RuntimeResult<InstructionOperands> {
         assert!(!(accepted_operand_types.0 == 0 && accepted_operand_types.1 != 0));
-        let operands_byte = self.memory[self.curr_addr + 1];
+        let addr = self.curr_addr as u16;
+        let instruction_byte = self.read_u8(addr)?;
+        let operands_byte = self.read_u8(addr.wrapping_add(1))?;
         let operand1 = (operands_byte >> 4) & 0xF;
         let operand2 = operands_byte & 0xF;
-        Ok(if accepted_operand_types.0 == 0 {
+        let is_extended = instruction_byte & EXTENDED_OPERANDS_FLAG != 0;
+        let (mode1, mode2) = if is_extended {
+            (
+                self.read_u8(addr.wrapping_add(2))?,
+                self.read_u8(addr.wrapping_add(3))?,
+            )
+        } else {
+            (PLAIN_MODE, PLAIN_MODE)
+        };
+        let mut word_addr = addr.wrapping_add(if is_extended { 4 } else { 2 });
+        let operands = if accepted_operand_types.0 == 0 {
             InstructionOperands::Zero
         } else if accepted_operand_types.1 == 0 {
-            InstructionOperands::One(
-                self.get_instruction_operand(accepted_operand_types.0, operand1)?,
-            )
+            InstructionOperands::One(self.get_instruction_operand(
+                accepted_operand_types.0,
+                operand1,
+                mode1,
+                &mut word_addr,
+            )?)
         } else {
             InstructionOperands::Two(
-                self.get_instruction_operand(accepted_operand_types.0, operand1)?,
-                self.get_instruction_operand(accepted_operand_types.1, operand2)?,
+                self.get_instruction_operand(
+                    accepted_operand_types.0,
+                    operand1,
+                    mode1,
+                    &mut word_addr,
+                )?,
+                self.get_instruction_operand(
+                    accepted_operand_types.1,
+                    operand2,
+                    mode2,
+                    &mut word_addr,
+                )?,
             )
-        })
+        };
+        // The size of an instruction is derived from its operands, so the extended
+        // encoding must be used exactly when some operand needs it.
+        let unused_mode = match operands {
+            InstructionOperands::Zero => mode1 | mode2,
+            InstructionOperands::One(_) => mode2,
+            InstructionOperands::Two(_, _) => PLAIN_MODE,
+        };
+        if unused_mode != PLAIN_MODE || operands.is_extended() != is_extended {
+            return Err(RuntimeError::InvalidInstruction {
+                err_address: self.curr_addr,
+                instruction: instruction_byte,
+            });
+        }
+        Ok(operands)
+    }
+
+    // Size in bytes of the instruction at `curr_addr`, if it can be decoded.
+    pub fn get_current_instruction_size(&self) -> Option<usize> {
+        let instruction_code = self.read_u8(self.curr_addr as u16).ok()? & !EXTENDED_OPERANDS_FLAG;
+        let info = self.instructions.get(instruction_code)?;
+        let operands = self.get_instruction_operands(info.accepted_operands).ok()?;
+        Some(operands.instruction_size())
     }
 
     pub fn execute_next_instruction(&mut self) -> RuntimeResult<()> {
+        self.watchpoint_hit = None;
+        self.breakpoint_hit = None;
         if self.has_finished {
             return Ok(());
         }
-        let instruction_code = self.read_u8(self.curr_addr as u16)?;
+        if let Some(limit) = self.max_instructions {
+            if self.executed_instructions >= limit {
+                return Err(RuntimeError::InstructionLimitExceeded {
+                    err_address: self.curr_addr,
+                    limit,
+                });
+            }
+        }
+        let instruction_byte = self.read_u8(self.curr_addr as u16)?;
+        if self.detect_loops {
+            let is_zero = instruction_byte == 0
+                && self.read_u8((self.curr_addr as u16).wrapping_add(1)).ok() == Some(0);
+            if self.loop_detector.zero_instruction(is_zero) >= MAX_ZERO_INSTRUCTIONS_IN_A_ROW {
+                return Err(RuntimeError::RanIntoZeroedMemory {
+                    err_address: self.curr_addr,
+                });
+            }
+        }
+        let instruction_code = instruction_byte & !EXTENDED_OPERANDS_FLAG;
         let Some(InstructionInfo {
+            name,
             accepted_operands,
+            cycles,
+            requires_register_pair,
             executor,
-            ..
-        }) = &INSTRUCTION_SET.get(instruction_code as usize)
+        }) = self.instructions.get(instruction_code).copied()
         else {
             return Err(RuntimeError::InvalidInstruction {
                 err_address: self.curr_addr,
-                instruction: instruction_code,
+                instruction: instruction_byte,
             });
         };
-        executor(self, self.get_instruction_operands(*accepted_operands)?)
+        let operands = self.get_instruction_operands(accepted_operands)?;
+        if let InstructionOperands::One(InstructionOperand::Reg(reg))
+        | InstructionOperands::Two(InstructionOperand::Reg(reg), _) = operands
+        {
+            if requires_register_pair && !self.config.has_register_pair(reg) {
+                return Err(RuntimeError::InvalidOperand {
+                    err_address: self.curr_addr,
+                    operand: reg,
+                });
+            }
+        }
+        if let Some(tracer) = &mut self.tracer {
+            let instruction = disassemble(name, operands);
+            let (registers, ps) = (self.registers, self.program_state_reg);
+            tracer.begin(
+                self.executed_instructions,
+                self.curr_addr,
+                instruction,
+                registers,
+                ps,
+            );
+        }
+        executor(self, operands)?;
+        if let Some(tracer) = &mut self.tracer {
+            tracer.finish(self.registers, self.program_state_reg);
+        }
+        self.executed_instructions += 1;
+        self.cycles += cycles + operands.cycles();
+        if self.detect_loops
+            && !self.has_finished
+            && self.loop_detector.is_repeated(self.machine_state())
+        {
+            return Err(RuntimeError::InfiniteLoop {
+                err_address: self.curr_addr,
+            });
+        }
+        if !self.has_finished {
+            self.check_breakpoints();
+        }
+        Ok(())
     }
 
-    pub fn read_from(&mut self, place_to_read_from: InstructionOperand) -> RuntimeResult<u16> {
-        Ok(match place_to_read_from {
-            InstructionOperand::Reg(reg) => self.registers[reg as usize],
-            InstructionOperand::Addr(reg) => self.read_u16(self.registers[reg as usize])?,
+    // Counts a hit of every breakpoint at `curr_addr` and evaluates their conditions.
+    // Called when the next instruction is reached, before it's executed.
+    pub fn check_breakpoints(&mut self) {
+        for i in 0..self.breakpoints.len() {
+            if self.breakpoints[i].address != self.curr_addr {
+                continue;
+            }
+            self.breakpoints[i].hits += 1;
+            let breakpoint = &self.breakpoints[i];
+            let condition_holds = breakpoint
+                .condition
+                .as_ref()
+                .is_none_or(|condition| condition.eval(self, breakpoint.hits) != 0);
+            if condition_holds && self.breakpoint_hit.is_none() {
+                self.breakpoint_hit = Some(i);
+            }
+        }
+    }
+
+    fn machine_state(&self) -> MachineState {
+        MachineState {
+            registers: self.registers,
+            program_state_reg: self.program_state_reg,
+            curr_addr: self.curr_addr,
+            input_changes: self.switches.changes() + self.keyboard.changes(),
+        }
+    }
+
+    fn write_to_code(&mut self, address: usize) -> RuntimeResult<()> {
+        let write = CodeWrite {
+            err_address: self.curr_addr,
+            address,
+        };
+        match self.code_protection {
+            CodeProtection::Off => {}
+            CodeProtection::ReadOnly => {
+                return Err(RuntimeError::WriteToCode {
+                    err_address: write.err_address,
+                    address,
+                })
+            }
+            CodeProtection::Log => {
+                if self.code_writes.len() < MAX_LOGGED_CODE_WRITES {
+                    self.code_writes.push(write);
+                }
+                self.modified_code.insert(address);
+            }
+        }
+        Ok(())
+    }
+
+    fn check_watchpoints(&mut self, kind: WatchKind, is_watched: impl Fn(&WatchTarget) -> bool) {
+        if self.watchpoint_hit.is_some() {
+            return;
+        }
+        if let Some(&watchpoint) = self
+            .watchpoints
+            .iter()
+            .find(|watchpoint| watchpoint.kind == kind && is_watched(&watchpoint.target))
+        {
+            self.watchpoint_hit = Some(WatchpointHit {
+                watchpoint,
+                address: self.curr_addr,
+            });
+        }
+    }
+
+    fn watch_write(&mut self, is_watched: impl Fn(&WatchTarget) -> bool, changed: bool) {
+        self.check_watchpoints(WatchKind::Write, &is_watched);
+        if changed {
+            self.check_watchpoints(WatchKind::Change, &is_watched);
+        }
+    }
+
+    fn watch_memory_read(&mut self, addr: u16, size: u16) {
+        let last = addr.saturating_add(size - 1);
+        self.check_watchpoints(WatchKind::Read, |target| target.contains_memory(addr, last));
+    }
+
+    // Register access by instructions, watchpoints see it.
+    fn get_register(&mut self, reg: u8) -> u16 {
+        self.check_watchpoints(WatchKind::Read, |target| {
+            *target == WatchTarget::Register(reg)
+        });
+        self.registers[reg as usize]
+    }
+
+    fn set_register(&mut self, reg: u8, value: u16) {
+        let changed = self.registers[reg as usize] != value;
+        self.watch_write(|target| *target == WatchTarget::Register(reg), changed);
+        self.registers[reg as usize] = value;
+    }
+
+    fn set_port(&mut self, port: u8, value: u16) {
+        let changed = self.display[port as usize] != value;
+        if changed {
+            self.loop_detector.side_effect();
+        }
+        self.watch_write(|target| *target == WatchTarget::Port(port), changed);
+        if let Some(tracer) = &mut self.tracer {
+            tracer.port_write(port, value);
+        }
+        self.display[port as usize] = value;
+    }
+
+    // Memory address of a memory operand, for `-(Rn)` the one below `Rn` by `step`.
+    // The register itself is changed by `step_register` after the access.
+    // Accesses through SP are checked against `stack_region`.
+    fn get_operand_address(
+        &mut self,
+        operand: InstructionOperand,
+        step: u16,
+    ) -> RuntimeResult<u16> {
+        let addr = match operand {
+            InstructionOperand::Addr(reg) | InstructionOperand::AddrInc(reg) => {
+                self.get_register(reg)
+            }
+            InstructionOperand::AddrDec(reg) => self.get_register(reg).wrapping_sub(step),
+            InstructionOperand::Indexed(reg, offset) => self.get_register(reg).wrapping_add(offset),
+            InstructionOperand::Absolute(addr) => addr,
+            _ => unreachable!("{operand} is not a memory operand"),
+        };
+        match operand {
+            InstructionOperand::Addr(4)
+            | InstructionOperand::AddrInc(4)
+            | InstructionOperand::AddrDec(4)
+            | InstructionOperand::Indexed(4, _) => self.check_stack_access(addr, step)?,
+            _ => {}
+        }
+        Ok(addr)
+    }
+
+    fn check_stack_access(&self, addr: u16, size: u16) -> RuntimeResult<()> {
+        let Some(region) = self.stack_region else {
+            return Ok(());
+        };
+        let (first, last) = (addr as usize, addr as usize + size as usize - 1);
+        if region.contains(first) && region.contains(last) {
+            return Ok(());
+        }
+        let err_address = self.curr_addr;
+        let address = if region.contains(first) { last } else { first };
+        if address < region.bottom {
+            Err(RuntimeError::StackOverflow {
+                err_address,
+                address,
+            })
+        } else {
+            Err(RuntimeError::StackUnderflow {
+                err_address,
+                address,
+            })
+        }
+    }
+
+    // Increments `(Rn)+` and decrements `-(Rn)` once the access succeeded,
+    // so a failed instruction leaves the register as it was.
+    fn step_register(&mut self, operand: InstructionOperand, step: u16) {
+        let (reg, value) = match operand {
             InstructionOperand::AddrInc(reg) => {
-                let num = self.read_u16(self.registers[reg as usize])?;
-                self.registers[reg as usize] = self.registers[reg as usize].wrapping_add(2);
-                num
+                (reg, self.registers[reg as usize].wrapping_add(step))
             }
+            InstructionOperand::AddrDec(reg) => {
+                (reg, self.registers[reg as usize].wrapping_sub(step))
+            }
+            _ => return,
+        };
+        self.set_register(reg, value);
+    }
+
+    fn read_memory_operand(
+        &mut self,
+        operand: InstructionOperand,
+        size: u16,
+    ) -> RuntimeResult<u16> {
+        let addr = self.get_operand_address(operand, size)?;
+        self.watch_memory_read(addr, size);
+        if size == 1 {
+            Ok(self.read_u8(addr)? as u16)
+        } else {
+            self.read_u16(addr)
+        }
+    }
+
+    fn read_input_port(&mut self, port: u8) -> u16 {
+        self.check_watchpoints(WatchKind::Read, |target| *target == WatchTarget::Port(port));
+        self.keyboard
+            .read(port)
+            .unwrap_or_else(|| self.switches.read(port))
+    }
+
+    fn trace_operand_read(&mut self, value: u16) -> u16 {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.operand_read(value);
+        }
+        value
+    }
+
+    // Reads the current value of an operand that is going to be overwritten.
+    // `(Rn)+` and `-(Rn)` are left as they are, so the following `write_to`
+    // uses the same address and steps the register once.
+    // A port gives its output value, so `bts p3, 5` keeps the other lamps of P3.
+    pub fn read_destination(&mut self, destination: InstructionOperand) -> RuntimeResult<u16> {
+        match destination {
+            InstructionOperand::AddrInc(_) | InstructionOperand::AddrDec(_) => self
+                .read_memory_operand(destination, 2)
+                .map(|value| self.trace_operand_read(value)),
+            InstructionOperand::Port(port) => {
+                Ok(self.trace_operand_read(self.display[port as usize]))
+            }
+            _ => self.read_from(destination),
+        }
+    }
+
+    // Byte variant of `read_destination`.
+    pub fn read_u8_destination(&mut self, destination: InstructionOperand) -> RuntimeResult<u8> {
+        match destination {
+            InstructionOperand::AddrInc(_) | InstructionOperand::AddrDec(_) => self
+                .read_memory_operand(destination, 1)
+                .map(|value| self.trace_operand_read(value) as u8),
+            InstructionOperand::Port(port) => {
+                Ok(self.trace_operand_read(self.display[port as usize]) as u8)
+            }
+            _ => self.read_u8_from(destination),
+        }
+    }
+
+    // A port gives its input value.
+    pub fn read_from(&mut self, place_to_read_from: InstructionOperand) -> RuntimeResult<u16> {
+        let value = match place_to_read_from {
+            InstructionOperand::Reg(reg) => self.get_register(reg),
             InstructionOperand::Number(num) => num,
-            InstructionOperand::Port(port) => self.display[port as usize],
-        })
+            InstructionOperand::Port(port) => self.read_input_port(port),
+            _ => {
+                let num = self.read_memory_operand(place_to_read_from, 2)?;
+                self.step_register(place_to_read_from, 2);
+                num
+            }
+        };
+        Ok(self.trace_operand_read(value))
     }
 
     pub fn write_to(
@@ -161,24 +771,71 @@ impl ProgramExecutor {
         num: u16,
     ) -> RuntimeResult<()> {
         match place_to_write_to {
-            InstructionOperand::Reg(reg) => self.registers[reg as usize] = num,
-            InstructionOperand::Addr(reg) | InstructionOperand::AddrInc(reg) => {
-                self.write_u16(self.registers[reg as usize], num)?
+            InstructionOperand::Reg(reg) => self.set_register(reg, num),
+            InstructionOperand::Port(port) => self.set_port(port, num),
+            InstructionOperand::Number(_) => {
+                return Err(RuntimeError::WritingToANumber {
+                    err_address: self.curr_addr,
+                })
+            }
+            _ => {
+                let addr = self.get_operand_address(place_to_write_to, 2)?;
+                self.write_u16(addr, num)?;
+                self.step_register(place_to_write_to, 2);
+            }
+        }
+        Ok(())
+    }
+
+    // Byte variant of `read_from`: registers and ports give their low byte,
+    // `(Rn)+` and `-(Rn)` step by 1 instead of 2.
+    pub fn read_u8_from(&mut self, place_to_read_from: InstructionOperand) -> RuntimeResult<u8> {
+        let value = match place_to_read_from {
+            InstructionOperand::Reg(reg) => self.get_register(reg) as u8,
+            InstructionOperand::Number(num) => num as u8,
+            InstructionOperand::Port(port) => self.read_input_port(port) as u8,
+            _ => {
+                let num = self.read_memory_operand(place_to_read_from, 1)? as u8;
+                self.step_register(place_to_read_from, 1);
+                num
+            }
+        };
+        Ok(self.trace_operand_read(value as u16) as u8)
+    }
+
+    // Byte variant of `write_to`. A byte written to a register or a port is widened
+    // by `extend(byte, old_value)`, memory receives just the byte.
+    pub fn write_u8_to(
+        &mut self,
+        place_to_write_to: InstructionOperand,
+        num: u8,
+        extend: impl Fn(u8, u16) -> u16,
+    ) -> RuntimeResult<()> {
+        match place_to_write_to {
+            InstructionOperand::Reg(reg) => {
+                self.set_register(reg, extend(num, self.registers[reg as usize]))
+            }
+            InstructionOperand::Port(port) => {
+                self.set_port(port, extend(num, self.display[port as usize]))
             }
-            InstructionOperand::Port(port) => self.display[port as usize] = num,
             InstructionOperand::Number(_) => {
                 return Err(RuntimeError::WritingToANumber {
                     err_address: self.curr_addr,
                 })
             }
+            _ => {
+                let addr = self.get_operand_address(place_to_write_to, 1)?;
+                self.write_u8(addr, num)?;
+                self.step_register(place_to_write_to, 1);
+            }
         }
         Ok(())
     }
 
     pub fn add_to_pc(&mut self, n: usize) {
         self.curr_addr = self.curr_addr.wrapping_add(n);
-        if self.curr_addr >= MAX_PROGRAM_SIZE {
-            self.curr_addr -= MAX_PROGRAM_SIZE;
+        if self.curr_addr >= self.config.memory_size {
+            self.curr_addr -= self.config.memory_size;
         }
     }
 }
@@ -189,6 +846,13 @@ pub enum RuntimeError {
     InvalidOperand { err_address: usize, operand: u8 },
     InvalidAddress { err_address: usize, address: usize },
     WritingToANumber { err_address: usize },
+    DivisionByZero { err_address: usize },
+    InfiniteLoop { err_address: usize },
+    RanIntoZeroedMemory { err_address: usize },
+    InstructionLimitExceeded { err_address: usize, limit: u64 },
+    WriteToCode { err_address: usize, address: usize },
+    StackOverflow { err_address: usize, address: usize },
+    StackUnderflow { err_address: usize, address: usize },
 }
 
 impl Display for RuntimeError {
@@ -218,6 +882,54 @@ impl Display for RuntimeError {
             RuntimeError::WritingToANumber { err_address } => {
                 write!(f, "0x{err_address:0x}: Trying to write to a number")
             }
+            RuntimeError::DivisionByZero { err_address } => {
+                write!(f, "0x{err_address:0x}: Division by zero")
+            }
+            RuntimeError::InfiniteLoop { err_address } => {
+                write!(
+                    f,
+                    "0x{err_address:0x}: Infinite loop: the machine returned to the same state"
+                )
+            }
+            RuntimeError::RanIntoZeroedMemory { err_address } => {
+                write!(
+                    f,
+                    "0x{err_address:0x}: Ran into zeroed memory, is `stop` missing?"
+                )
+            }
+            RuntimeError::WriteToCode {
+                err_address,
+                address,
+            } => {
+                write!(
+                    f,
+                    "0x{err_address:0x}: Writing to the code of the program at `0x{address:x}`"
+                )
+            }
+            RuntimeError::StackOverflow {
+                err_address,
+                address,
+            } => {
+                write!(
+                    f,
+                    "0x{err_address:0x}: Stack overflow: `0x{address:x}` is below the stack"
+                )
+            }
+            RuntimeError::StackUnderflow {
+                err_address,
+                address,
+            } => {
+                write!(
+                    f,
+                    "0x{err_address:0x}: Stack underflow: `0x{address:x}` is above the stack"
+                )
+            }
+            RuntimeError::InstructionLimitExceeded { err_address, limit } => {
+                write!(
+                    f,
+                    "0x{err_address:0x}: The program didn't stop after {limit} instructions"
+                )
+            }
         }
     }
 }
@@ -0,0 +1,231 @@
+// Robustness check of the executor: runs random memory images from random states
+// and fails on the first panic. Every run is reproducible from its seed.
+// `cargo test` runs it with a fixed seed, `rustanel --fuzz` with any number of runs.
+
+use crate::breakpoints::Breakpoint;
+use crate::executor::{CodeProtection, ProgramExecutor, StackRegion};
+use crate::instruction_set::{
+    InstructionInfo, ABSOLUTE_MASK, ABSOLUTE_MODE, ADDR_DEC_MASK, ADDR_DEC_MODE, ADDR_INC_MASK,
+    ADDR_MASK, EXTENDED_OPERANDS_FLAG, HIGH_PORT_MODES, INDEXED_MASK, INDEXED_MODE, NUMBER_MASK,
+    NUMBER_OPERAND_CODE, PLAIN_MODE, PORT_MASK, REG_MASK,
+};
+use crate::machine::{MachineConfig, PRESETS};
+use crate::trace::Tracer;
+use crate::watchpoints::{WatchKind, WatchTarget, Watchpoint};
+use std::panic;
+
+const STEPS_PER_RUN: usize = 1000;
+// Generating instructions for all of a large memory is slow, so only this many bytes
+// at both ends of memory get them. Runs rarely get far from where they started.
+const CODE_WINDOW: usize = 0x800;
+
+// xorshift64*, good enough to cover the instruction encoding.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // The state must not be zero.
+        Self(seed ^ 0x9E37_79B9_7F4A_7C15)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn chance(&mut self, one_in: usize) -> bool {
+        self.below(one_in) == 0
+    }
+
+    // Mostly addresses inside of memory, so that memory operands don't fail right away.
+    fn word(&mut self, config: &MachineConfig) -> u16 {
+        if self.chance(8) {
+            self.next() as u16
+        } else {
+            self.below(config.memory_size) as u16
+        }
+    }
+}
+
+// The 4-bit code, the mode and the words of a random operand accepted by `mask`.
+fn random_operand(rng: &mut Rng, config: &MachineConfig, mask: u8) -> (u8, u8, Vec<u16>) {
+    let kinds: Vec<u8> = (0..8)
+        .map(|i| 1 << i)
+        .filter(|kind| mask & kind != 0)
+        .collect();
+    let reg = rng.below(5) as u8;
+    match kinds[rng.below(kinds.len())] {
+        REG_MASK => (reg, PLAIN_MODE, vec![]),
+        ADDR_MASK => (5 + reg, PLAIN_MODE, vec![]),
+        ADDR_INC_MASK => (10 + reg, PLAIN_MODE, vec![]),
+        PORT_MASK => {
+            let port = rng.below(config.ports() + 1) as u8;
+            (port & 0xF, port & HIGH_PORT_MODES, vec![])
+        }
+        NUMBER_MASK => (NUMBER_OPERAND_CODE, PLAIN_MODE, vec![rng.word(config)]),
+        ADDR_DEC_MASK => (reg, ADDR_DEC_MODE, vec![]),
+        INDEXED_MASK => {
+            let offset = rng.below(64) as i16 - 32;
+            (reg, INDEXED_MODE, vec![offset as u16])
+        }
+        ABSOLUTE_MASK => (0, ABSOLUTE_MODE, vec![rng.word(config)]),
+        _ => unreachable!(),
+    }
+}
+
+// A correctly encoded instruction, so that programs run for a while.
+fn random_instruction(
+    rng: &mut Rng,
+    config: &MachineConfig,
+    instructions: &[(u8, InstructionInfo)],
+) -> Vec<u8> {
+    let (opcode, info) = instructions[rng.below(instructions.len())];
+    let accepted = info.accepted_operands;
+    let mut operands = vec![];
+    for mask in [accepted.0, accepted.1].into_iter().take(accepted.count()) {
+        operands.push(random_operand(rng, config, mask));
+    }
+    let code = |i: usize| operands.get(i).map_or(0, |op| op.0);
+    let mode = |i: usize| operands.get(i).map_or(PLAIN_MODE, |op| op.1);
+    let is_extended = mode(0) != PLAIN_MODE || mode(1) != PLAIN_MODE;
+    let mut binary = vec![opcode, (code(0) << 4) | code(1)];
+    if is_extended {
+        binary[0] |= EXTENDED_OPERANDS_FLAG;
+        binary.extend([mode(0), mode(1)]);
+    }
+    for (_, _, words) in &operands {
+        for word in words {
+            binary.extend(word.to_be_bytes());
+        }
+    }
+    binary
+}
+
+fn random_executor(rng: &mut Rng) -> ProgramExecutor {
+    let config = PRESETS[rng.below(PRESETS.len())].1;
+    let memory_size = config.memory_size;
+    let mut executor = ProgramExecutor::default();
+    executor.set_machine_config(config);
+    executor.strict_division = rng.chance(2);
+    executor.detect_loops = rng.chance(2);
+    executor.code_protection = [
+        CodeProtection::Off,
+        CodeProtection::ReadOnly,
+        CodeProtection::Log,
+    ][rng.below(3)];
+    if rng.chance(2) {
+        executor.stack_region = Some(StackRegion::at_the_end_of_memory(
+            2 + rng.below(StackRegion::top(memory_size) - 1),
+            memory_size,
+        ));
+    }
+    if rng.chance(4) {
+        executor.watchpoints.push(Watchpoint {
+            target: WatchTarget::Memory(rng.next() as u16, rng.next() as u16),
+            kind: WatchKind::Change,
+        });
+    }
+    if rng.chance(4) {
+        let condition = "[sp] / r0 % (pc - 3) != p15 >> r1";
+        let breakpoint = Breakpoint::new(rng.below(memory_size), condition, &executor.config());
+        executor.breakpoints.push(breakpoint.unwrap());
+    }
+    if rng.chance(4) {
+        let ranges = (0..rng.below(3))
+            .map(|_| {
+                let start = rng.below(memory_size);
+                start..start + rng.below(memory_size)
+            })
+            .collect();
+        executor.tracer = Some(Tracer::with_address_ranges(ranges));
+    }
+    // Valid instructions with some noise between them and some random bytes on top.
+    let instructions: Vec<_> = executor
+        .instructions()
+        .iter()
+        .map(|(opcode, info)| (opcode, *info))
+        .collect();
+    let mut memory = vec![0; memory_size];
+    let mut instruction_addresses = vec![];
+    let mut addr = 0;
+    while addr < memory_size {
+        if addr >= CODE_WINDOW && addr < memory_size.saturating_sub(CODE_WINDOW) {
+            addr = memory_size - CODE_WINDOW;
+        }
+        instruction_addresses.push(addr);
+        let binary = if rng.chance(16) {
+            vec![rng.next() as u8]
+        } else {
+            random_instruction(rng, &config, &instructions)
+        };
+        for byte in binary {
+            if addr < memory_size {
+                memory[addr] = byte;
+                addr += 1;
+            }
+        }
+    }
+    for _ in 0..rng.below(16) {
+        memory[rng.below(memory_size)] = rng.next() as u8;
+    }
+    executor.load_program(&memory, rng.below(memory_size + 1));
+    executor.prepare_for_a_new_run();
+    for reg in &mut executor.registers {
+        *reg = rng.word(&config);
+    }
+    executor.program_state_reg = rng.next() as u16;
+    // Edges of memory are the most interesting places to start from.
+    executor.curr_addr = if rng.chance(4) {
+        memory_size - 1 - rng.below(4)
+    } else if rng.chance(4) {
+        rng.below(memory_size)
+    } else {
+        instruction_addresses[rng.below(instruction_addresses.len())]
+    };
+    executor
+}
+
+fn run_random_image(seed: u64) {
+    let mut rng = Rng::new(seed);
+    let mut executor = random_executor(&mut rng);
+    for _ in 0..STEPS_PER_RUN {
+        if executor.has_finished {
+            break;
+        }
+        executor.get_current_instruction_size();
+        if executor.execute_next_instruction().is_err() {
+            break;
+        }
+    }
+}
+
+// Returns the seed of the first run that panicked.
+pub fn run(runs: u64, seed: u64) -> Result<(), u64> {
+    for run in 0..runs {
+        let run_seed = seed.wrapping_add(run);
+        if panic::catch_unwind(|| run_random_image(run_seed)).is_err() {
+            return Err(run_seed);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    // A fixed seed, so that a failure can be rerun with `--fuzz 1 <seed>`.
+    const SEED: u64 = 0x5EED;
+    const RUNS: u64 = 5_000;
+
+    #[test]
+    fn random_images_dont_panic() {
+        if let Err(seed) = super::run(RUNS, SEED) {
+            panic!("The executor panicked, rerun with `--fuzz 1 {seed}`");
+        }
+    }
+}
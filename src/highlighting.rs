@@ -1,39 +1,39 @@
 use crate::compiler::ErrorsHighlightInfo;
-use crate::instruction_set::INSTRUCTION_SET;
 use eframe::egui;
-use eframe::egui::ahash::HashSetExt;
 use eframe::egui::{Color32, Stroke, TextFormat};
-use eframe::epaint::ahash::HashSet;
 use egui::text::LayoutJob;
 use enum_map::Enum;
-use lazy_static::lazy_static;
 
 /// Add syntax highlighting to a code string.
 ///
 /// The results are memoized, so you can call this every frame without performance penalty.
+/// `keywords` are the sorted instruction names, see `Compiler::instruction_names`.
 pub fn highlight(
     ctx: &egui::Context,
     theme: &CodeTheme,
     code: &str,
     errors: &ErrorsHighlightInfo,
+    keywords: &[&'static str],
 ) -> LayoutJob {
     type HighlightCache = egui::util::cache::FrameCache<LayoutJob, Highlighter>;
 
     ctx.memory_mut(|mem| {
         mem.caches
             .cache::<HighlightCache>()
-            .get((theme, code, errors))
+            .get((theme, code, errors, keywords))
     })
 }
 
-impl egui::util::cache::ComputerMut<(&CodeTheme, &str, &ErrorsHighlightInfo), LayoutJob>
-    for Highlighter
-{
-    fn compute(
-        &mut self,
-        (theme, code, errors): (&CodeTheme, &str, &ErrorsHighlightInfo),
-    ) -> LayoutJob {
-        self.highlight(theme, code, errors)
+type HighlightKey<'a> = (
+    &'a CodeTheme,
+    &'a str,
+    &'a ErrorsHighlightInfo,
+    &'a [&'static str],
+);
+
+impl egui::util::cache::ComputerMut<HighlightKey<'_>, LayoutJob> for Highlighter {
+    fn compute(&mut self, (theme, code, errors, keywords): HighlightKey<'_>) -> LayoutJob {
+        self.highlight(theme, code, errors, keywords)
     }
 }
 
@@ -140,21 +140,17 @@ impl CodeTheme {
 }
 
 impl Highlighter {
-    fn highlight(&self, theme: &CodeTheme, code: &str, errors: &ErrorsHighlightInfo) -> LayoutJob {
-        self.highlight_impl(theme, code, errors)
+    fn highlight(
+        &self,
+        theme: &CodeTheme,
+        code: &str,
+        errors: &ErrorsHighlightInfo,
+        keywords: &[&'static str],
+    ) -> LayoutJob {
+        self.highlight_impl(theme, code, errors, keywords)
     }
 }
 
-lazy_static! {
-    static ref ASM_KEYWORDS_SET: HashSet<&'static str> = {
-        let mut res = HashSet::new();
-        for info in INSTRUCTION_SET {
-            res.insert(info.name);
-        }
-        res
-    };
-}
-
 pub fn wrapping_parse(mut text: &str) -> Option<u16> {
     let sign = if text.starts_with('-') {
         text = &text[1..];
@@ -184,8 +180,10 @@ pub fn wrapping_parse(mut text: &str) -> Option<u16> {
 struct Highlighter {}
 
 impl Highlighter {
-    fn is_keyword(word: &str) -> bool {
-        ASM_KEYWORDS_SET.contains(&word.to_ascii_lowercase().as_str())
+    fn is_keyword(keywords: &[&'static str], word: &str) -> bool {
+        keywords
+            .binary_search(&word.to_ascii_lowercase().as_str())
+            .is_ok()
     }
 
     fn highlight_impl(
@@ -193,6 +191,7 @@ impl Highlighter {
         theme: &CodeTheme,
         mut text: &str,
         errors: &ErrorsHighlightInfo,
+        keywords: &[&'static str],
     ) -> LayoutJob {
         let mut job = Vec::new();
         let initial_text = text;
@@ -223,13 +222,13 @@ impl Highlighter {
             } else if text.starts_with(|c: char| c.is_alphanumeric()) {
                 let mut end = text
                     .find(|c: char| !c.is_alphanumeric())
-                    .unwrap_or_else(|| text.len());
+                    .unwrap_or(text.len());
                 let mut word = &text[..end];
-                let tt = if text[end..].chars().next() == Some(':') {
+                let tt = if text[end..].starts_with(':') {
                     end += 1;
                     word = &text[..end];
                     TokenType::Label
-                } else if Self::is_keyword(word) {
+                } else if Self::is_keyword(keywords, word) {
                     TokenType::Keyword
                 } else if wrapping_parse(word).is_some() {
                     TokenType::Number
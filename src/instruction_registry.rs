@@ -0,0 +1,227 @@
+// Instructions known to the compiler and the executor, by opcode.
+//
+// The default registry has the built-in `INSTRUCTION_SET`. Custom instructions, e.g. for
+// an exercise, are registered before building a `Compiler` and a `ProgramExecutor`:
+//
+//     let mut instructions = InstructionRegistry::default();
+//     let opcode = instructions.register(InstructionInfo { name: "swap", .. })?;
+//     let instructions = Rc::new(instructions);
+//     let compiler = Compiler::with_instructions(instructions.clone());
+//     let executor = ProgramExecutor::with_instructions(instructions);
+//
+// Several instructions may share a name if they take different operands, like `bt r0, 3`
+// and `bt p3, 5`.
+
+use crate::instruction_set::{InstructionInfo, EXTENDED_OPERANDS_FLAG, INSTRUCTION_SET};
+use std::fmt::{Display, Formatter};
+
+// The high bit of the first instruction byte flags extended operands.
+pub const MAX_OPCODES: usize = EXTENDED_OPERANDS_FLAG as usize;
+
+#[derive(Clone)]
+pub struct InstructionRegistry {
+    instructions: Vec<Option<InstructionInfo>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RegistryError {
+    InvalidName {
+        name: String,
+    },
+    InvalidOperands {
+        name: String,
+    },
+    InvalidOpcode {
+        opcode: u8,
+    },
+    OpcodeTaken {
+        opcode: u8,
+        name: String,
+    },
+    NoFreeOpcodes {
+        name: String,
+    },
+    WrongNumberOfOperands {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+    SameOperands {
+        name: String,
+        opcode: u8,
+    },
+}
+
+impl Display for RegistryError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegistryError::InvalidName { name } => write!(
+                f,
+                "Invalid instruction name `{name}`, expected lowercase letters and digits"
+            ),
+            RegistryError::InvalidOperands { name } => write!(
+                f,
+                "`{name}` accepts a second operand, but not the first one"
+            ),
+            RegistryError::InvalidOpcode { opcode } => write!(
+                f,
+                "Invalid opcode 0x{opcode:02x}, opcodes are below 0x{MAX_OPCODES:02x}"
+            ),
+            RegistryError::OpcodeTaken { opcode, name } => {
+                write!(f, "Opcode 0x{opcode:02x} is already taken by `{name}`")
+            }
+            RegistryError::NoFreeOpcodes { name } => {
+                write!(f, "No free opcodes left for `{name}`")
+            }
+            RegistryError::WrongNumberOfOperands {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Other variants of `{name}` take {expected} operands, this one takes {found}"
+            ),
+            RegistryError::SameOperands { name, opcode } => write!(
+                f,
+                "`{name}` with opcode 0x{opcode:02x} already accepts some of these operands"
+            ),
+        }
+    }
+}
+
+impl Default for InstructionRegistry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        for (opcode, info) in INSTRUCTION_SET {
+            registry
+                .register_at(opcode, info)
+                .expect("The built-in instructions don't conflict");
+        }
+        registry
+    }
+}
+
+impl InstructionRegistry {
+    // No instructions at all, not even `stop`.
+    pub fn empty() -> Self {
+        Self {
+            instructions: vec![None; MAX_OPCODES],
+        }
+    }
+
+    pub fn get(&self, opcode: u8) -> Option<&InstructionInfo> {
+        self.instructions.get(opcode as usize)?.as_ref()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u8, &InstructionInfo)> {
+        self.instructions
+            .iter()
+            .enumerate()
+            .filter_map(|(opcode, info)| Some((opcode as u8, info.as_ref()?)))
+    }
+
+    // Takes the highest free opcode, so that custom instructions don't collide with
+    // built-ins added later, which grow upward.
+    pub fn register(&mut self, info: InstructionInfo) -> Result<u8, RegistryError> {
+        let opcode = self
+            .instructions
+            .iter()
+            .rposition(Option::is_none)
+            .ok_or_else(|| RegistryError::NoFreeOpcodes {
+                name: info.name.to_string(),
+            })?;
+        self.register_at(opcode as u8, info)?;
+        Ok(opcode as u8)
+    }
+
+    pub fn register_at(&mut self, opcode: u8, info: InstructionInfo) -> Result<(), RegistryError> {
+        let name = info.name.to_string();
+        let is_valid_name = info.name.starts_with(|c: char| c.is_ascii_lowercase())
+            && info
+                .name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit());
+        if !is_valid_name {
+            return Err(RegistryError::InvalidName { name });
+        }
+        let accepted = info.accepted_operands;
+        if accepted.0 == 0 && accepted.1 != 0 {
+            return Err(RegistryError::InvalidOperands { name });
+        }
+        match self.instructions.get(opcode as usize) {
+            None => return Err(RegistryError::InvalidOpcode { opcode }),
+            Some(Some(taken)) => {
+                return Err(RegistryError::OpcodeTaken {
+                    opcode,
+                    name: taken.name.to_string(),
+                })
+            }
+            Some(None) => {}
+        }
+        for (other_opcode, other) in self.iter().filter(|(_, other)| other.name == info.name) {
+            let other_accepted = other.accepted_operands;
+            if other_accepted.count() != accepted.count() {
+                return Err(RegistryError::WrongNumberOfOperands {
+                    name,
+                    expected: other_accepted.count(),
+                    found: accepted.count(),
+                });
+            }
+            // Operands that both variants accept would always go to the first one.
+            let overlaps = |mask: u8, other_mask: u8| mask == 0 || mask & other_mask != 0;
+            if overlaps(accepted.0, other_accepted.0) && overlaps(accepted.1, other_accepted.1) {
+                return Err(RegistryError::SameOperands {
+                    name,
+                    opcode: other_opcode,
+                });
+            }
+        }
+        self.instructions[opcode as usize] = Some(info);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::executor::ProgramExecutor;
+    use crate::instruction_set::{AcceptedOperandTypes, REG_MASK};
+    use std::rc::Rc;
+
+    // Swaps the bytes of a register.
+    const SWAP: InstructionInfo = InstructionInfo {
+        name: "swap",
+        accepted_operands: AcceptedOperandTypes(REG_MASK, 0),
+        cycles: 1,
+        requires_register_pair: false,
+        executor: |executor, operands| {
+            let (op1, size) = operands.one();
+            let value = executor.read_destination(op1)?;
+            executor.write_to(op1, value.swap_bytes())?;
+            executor.set_overflow(false);
+            executor.add_to_pc(size);
+            Ok(())
+        },
+    };
+
+    #[test]
+    fn custom_instructions_compile_and_run() {
+        let mut instructions = InstructionRegistry::default();
+        assert_eq!(instructions.register(SWAP), Ok(MAX_OPCODES as u8 - 1));
+        let instructions = Rc::new(instructions);
+
+        let mut compiler = Compiler::with_instructions(instructions.clone());
+        assert!(compiler.instruction_names().contains(&"swap"));
+        compiler.compile_code("mov r1, 0x1234\nswap r1\nstop\n");
+        assert!(compiler.errors.is_empty());
+
+        let mut executor = ProgramExecutor::with_instructions(instructions);
+        executor.prepare_for_a_new_run();
+        executor.load_program(&compiler.program, compiler.code_size);
+        while !executor.has_finished {
+            executor.execute_next_instruction().unwrap();
+        }
+        assert_eq!(executor.registers[1], 0x3412);
+    }
+}
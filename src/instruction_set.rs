@@ -1,13 +1,39 @@
-use crate::compiler::MAX_PROGRAM_SIZE;
 use crate::executor::{ProgramExecutor, RuntimeError, RuntimeResult};
 use std::fmt::Display;
 
 pub const NUMBER_OPERAND_CODE: u8 = 0xF;
-pub const REG_MASK: u8 = 0b00001;
-pub const ADDR_MASK: u8 = 0b00010;
-pub const ADDR_INC_MASK: u8 = 0b00100;
-pub const PORT_MASK: u8 = 0b01000;
-pub const NUMBER_MASK: u8 = 0b10000;
+pub const REG_MASK: u8 = 0b00000001;
+pub const ADDR_MASK: u8 = 0b00000010;
+pub const ADDR_INC_MASK: u8 = 0b00000100;
+pub const PORT_MASK: u8 = 0b00001000;
+pub const NUMBER_MASK: u8 = 0b00010000;
+pub const ADDR_DEC_MASK: u8 = 0b00100000;
+pub const INDEXED_MASK: u8 = 0b01000000;
+pub const ABSOLUTE_MASK: u8 = 0b10000000;
+// Every way to address memory.
+pub const MEMORY_MASK: u8 =
+    ADDR_MASK | ADDR_INC_MASK | ADDR_DEC_MASK | INDEXED_MASK | ABSOLUTE_MASK;
+
+// Extended operand encoding.
+//
+// A plain instruction is `opcode, operands` with an optional number word after them.
+// The operands byte has one 4-bit code per operand: 0..=4 - Rn, 5..=9 - (Rn),
+// 10..=14 - (Rn)+, 15 - a number, or a port number for port operands.
+//
+// When `EXTENDED_OPERANDS_FLAG` is set in the opcode byte, two mode bytes follow the
+// operands byte, one per operand. A zero mode keeps the plain meaning of the 4-bit code,
+// other modes take the register from the code (0..=4) and may need a word of their own.
+// Operand words follow the mode bytes in the order of operands.
+pub const EXTENDED_OPERANDS_FLAG: u8 = 0x80;
+pub const PLAIN_MODE: u8 = 0;
+// -(Rn)
+pub const ADDR_DEC_MODE: u8 = 1;
+// offset(Rn), followed by the offset word
+pub const INDEXED_MODE: u8 = 2;
+// (address), followed by the address word
+pub const ABSOLUTE_MODE: u8 = 3;
+// Ports above p15: the mode is the high 4 bits of the port number, the code is the low ones.
+pub const HIGH_PORT_MODES: u8 = 0xF0;
 
 #[derive(Clone, Copy)]
 pub struct AcceptedOperandTypes(pub u8, pub u8);
@@ -26,10 +52,14 @@ impl AcceptedOperandTypes {
 
 pub fn get_expected_operand_types_string(mask: u8) -> String {
     let mut expected = vec![];
-    const OPERAND_TYPES: [(u8, &'static str); 5] = [
+    const OPERAND_TYPES: [(u8, &str); 8] = [
         (REG_MASK, "register"),
-        (ADDR_MASK, "address"),
-        (ADDR_INC_MASK, "address++"),
+        // Memory operands are shown the way they are written.
+        (ADDR_MASK, "(register)"),
+        (ADDR_INC_MASK, "(register)+"),
+        (ADDR_DEC_MASK, "-(register)"),
+        (INDEXED_MASK, "offset(register)"),
+        (ABSOLUTE_MASK, "(address)"),
         (PORT_MASK, "port"),
         (NUMBER_MASK, "number"),
     ];
@@ -61,10 +91,48 @@ pub enum InstructionOperand {
     Reg(u8),
     Addr(u8),
     AddrInc(u8),
+    AddrDec(u8),
+    Indexed(u8, u16),
+    Absolute(u16),
     Port(u8),
     Number(u16),
 }
 
+impl InstructionOperand {
+    // Operands that can't be encoded with a 4-bit code alone.
+    pub fn is_extended(&self) -> bool {
+        matches!(
+            self,
+            InstructionOperand::AddrDec(_)
+                | InstructionOperand::Indexed(_, _)
+                | InstructionOperand::Absolute(_)
+        ) || matches!(self, InstructionOperand::Port(port) if port & HIGH_PORT_MODES != 0)
+    }
+
+    // Operands followed by a word of their own.
+    pub fn has_word(&self) -> bool {
+        matches!(
+            self,
+            InstructionOperand::Indexed(_, _)
+                | InstructionOperand::Absolute(_)
+                | InstructionOperand::Number(_)
+        )
+    }
+
+    // Extra cycles for fetching an operand word and for accessing memory or a port.
+    pub fn cycles(&self) -> u64 {
+        match self {
+            InstructionOperand::Reg(_) => 0,
+            InstructionOperand::Number(_)
+            | InstructionOperand::Port(_)
+            | InstructionOperand::Addr(_)
+            | InstructionOperand::AddrInc(_)
+            | InstructionOperand::AddrDec(_) => 1,
+            InstructionOperand::Indexed(_, _) | InstructionOperand::Absolute(_) => 2,
+        }
+    }
+}
+
 impl Display for InstructionOperand {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let str = match self {
@@ -72,6 +140,9 @@ impl Display for InstructionOperand {
             InstructionOperand::Reg(r) => format!("R{r}"),
             InstructionOperand::Addr(r) => format!("(R{r})"),
             InstructionOperand::AddrInc(r) => format!("(R{r})+"),
+            InstructionOperand::AddrDec(r) => format!("-(R{r})"),
+            InstructionOperand::Indexed(r, _) => format!("offset(R{r})"),
+            InstructionOperand::Absolute(_) => "(address)".to_string(),
             InstructionOperand::Port(p) => format!("P{p}"),
             InstructionOperand::Number(_) => "number".to_string(),
         };
@@ -95,59 +166,45 @@ impl InstructionOperands {
         }
     }
 
-    pub fn instruction_size(&self) -> usize {
-        match self {
-            InstructionOperands::Zero => 2,
-            InstructionOperands::One(op1) => {
-                if let InstructionOperand::Number(_) = op1 {
-                    4
-                } else {
-                    2
-                }
-            }
-            InstructionOperands::Two(op1, op2) => {
-                if let InstructionOperand::Number(_) = op1 {
-                    4
-                } else if let InstructionOperand::Number(_) = op2 {
-                    4
-                } else {
-                    2
-                }
-            }
+    fn as_vec(&self) -> Vec<InstructionOperand> {
+        match *self {
+            InstructionOperands::Zero => vec![],
+            InstructionOperands::One(op1) => vec![op1],
+            InstructionOperands::Two(op1, op2) => vec![op1, op2],
         }
     }
 
+    pub fn is_extended(&self) -> bool {
+        self.as_vec().iter().any(InstructionOperand::is_extended)
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.as_vec().iter().map(InstructionOperand::cycles).sum()
+    }
+
+    pub fn instruction_size(&self) -> usize {
+        let operands = self.as_vec();
+        let words = operands.iter().filter(|op| op.has_word()).count();
+        2 + 2 * (self.is_extended() as usize) + 2 * words
+    }
+
     pub fn zero(&self) -> usize {
         match self {
-            InstructionOperands::Zero => 2,
+            InstructionOperands::Zero => self.instruction_size(),
             _ => panic!("Expected 0 operands, found {}", self.count()),
         }
     }
 
     pub fn one(&self) -> (InstructionOperand, usize) {
         match self {
-            &InstructionOperands::One(op) => (
-                op,
-                match op {
-                    InstructionOperand::Number(_) => 4,
-                    _ => 2,
-                },
-            ),
+            &InstructionOperands::One(op) => (op, self.instruction_size()),
             _ => panic!("Expected 1 operand, found {}", self.count()),
         }
     }
 
     pub fn two(&self) -> (InstructionOperand, InstructionOperand, usize) {
         match self {
-            &InstructionOperands::Two(op1, op2) => (
-                op1,
-                op2,
-                match (op1, op2) {
-                    (InstructionOperand::Number(_), _) => 4,
-                    (_, InstructionOperand::Number(_)) => 4,
-                    _ => 2,
-                },
-            ),
+            &InstructionOperands::Two(op1, op2) => (op1, op2, self.instruction_size()),
             _ => panic!("Expected 2 operands, found {}", self.count()),
         }
     }
@@ -156,9 +213,15 @@ impl InstructionOperands {
 // Executes a binary instruction
 pub type InstructionExecutor = fn(&mut ProgramExecutor, InstructionOperands) -> RuntimeResult<()>;
 
+#[derive(Clone, Copy)]
 pub struct InstructionInfo {
     pub name: &'static str,
     pub accepted_operands: AcceptedOperandTypes,
+    // Cycles of the instruction itself, operands add `InstructionOperand::cycles`.
+    pub cycles: u64,
+    // The first operand is a register that is written together with the next one,
+    // like the 32-bit product of `mulw`.
+    pub requires_register_pair: bool,
     pub executor: InstructionExecutor,
 }
 
@@ -166,7 +229,7 @@ macro_rules! one_operand_instruction {
     ($f:expr) => {
         |executor: &mut ProgramExecutor, operands: InstructionOperands| {
             let (op1, size) = operands.one();
-            let (res, overflow) = ($f)(executor.read_from(op1)?);
+            let (res, overflow) = ($f)(executor.read_destination(op1)?);
             executor.write_to(op1, res)?;
             executor.set_overflow(overflow);
             executor.add_to_pc(size);
@@ -179,7 +242,8 @@ macro_rules! two_operands_instruction {
     ($f:expr) => {
         |executor: &mut ProgramExecutor, operands: InstructionOperands| {
             let (op1, op2, size) = operands.two();
-            let (res, overflow) = ($f)(executor.read_from(op1)?, executor.read_from(op2)?);
+            let b = executor.read_from(op2)?;
+            let (res, overflow) = ($f)(executor.read_destination(op1)?, b);
             executor.write_to(op1, res)?;
             executor.set_overflow(overflow);
             executor.add_to_pc(size);
@@ -188,172 +252,823 @@ macro_rules! two_operands_instruction {
     };
 }
 
-pub const INSTRUCTION_SET: [InstructionInfo; 18] = [
-    InstructionInfo {
-        name: "nop",
-        accepted_operands: AcceptedOperandTypes(0, 0),
-        executor: |executor, operands| {
-            let size = operands.zero();
+// `$f` also gets the carry flag. The carry it returns is stored both in the carry flag
+// and, as in other arithmetic instructions, in the overflow flag.
+macro_rules! carry_instruction {
+    ($f:expr) => {
+        |executor: &mut ProgramExecutor, operands: InstructionOperands| {
+            let (op1, op2, size) = operands.two();
+            let b = executor.read_from(op2)?;
+            let a = executor.read_destination(op1)?;
+            let (res, carry) = ($f)(a, b, executor.carry());
+            executor.write_to(op1, res)?;
+            executor.set_carry(carry);
+            executor.set_overflow(carry);
             executor.add_to_pc(size);
             Ok(())
-        },
-    },
-    InstructionInfo {
-        name: "mov",
-        accepted_operands: AcceptedOperandTypes(
-            REG_MASK | ADDR_MASK | ADDR_INC_MASK,
-            REG_MASK | ADDR_MASK | ADDR_INC_MASK | NUMBER_MASK,
-        ),
-        executor: two_operands_instruction!(|_a, b| (b, false)),
-    },
-    InstructionInfo {
-        name: "add",
-        accepted_operands: AcceptedOperandTypes(
-            REG_MASK | ADDR_MASK | ADDR_INC_MASK,
-            REG_MASK | ADDR_MASK | ADDR_INC_MASK | NUMBER_MASK,
-        ),
-        executor: two_operands_instruction!(u16::overflowing_add),
-    },
-    InstructionInfo {
-        name: "sub",
-        accepted_operands: AcceptedOperandTypes(
-            REG_MASK | ADDR_MASK | ADDR_INC_MASK,
-            REG_MASK | ADDR_MASK | ADDR_INC_MASK | NUMBER_MASK,
-        ),
-        executor: two_operands_instruction!(u16::overflowing_sub),
-    },
-    InstructionInfo {
-        name: "mul",
-        accepted_operands: AcceptedOperandTypes(
-            REG_MASK | ADDR_MASK | ADDR_INC_MASK,
-            REG_MASK | ADDR_MASK | ADDR_INC_MASK | NUMBER_MASK,
-        ),
-        executor: two_operands_instruction!(u16::overflowing_mul),
-    },
-    InstructionInfo {
-        name: "div",
-        accepted_operands: AcceptedOperandTypes(
-            REG_MASK | ADDR_MASK | ADDR_INC_MASK,
-            REG_MASK | ADDR_MASK | ADDR_INC_MASK | NUMBER_MASK,
-        ),
-        executor: two_operands_instruction!(|a, b| if b != 0 {
-            u16::overflowing_div(a, b)
-        } else {
-            (0, true)
-        }),
-    },
-    InstructionInfo {
-        name: "and",
-        accepted_operands: AcceptedOperandTypes(
-            REG_MASK | ADDR_MASK | ADDR_INC_MASK,
-            REG_MASK | ADDR_MASK | ADDR_INC_MASK | NUMBER_MASK,
-        ),
-        executor: two_operands_instruction!(|a, b| (a & b, false)),
-    },
-    InstructionInfo {
-        name: "or",
-        accepted_operands: AcceptedOperandTypes(
-            REG_MASK | ADDR_MASK | ADDR_INC_MASK,
-            REG_MASK | ADDR_MASK | ADDR_INC_MASK | NUMBER_MASK,
-        ),
-        executor: two_operands_instruction!(|a, b| (a | b, false)),
-    },
-    InstructionInfo {
-        name: "xor",
-        accepted_operands: AcceptedOperandTypes(
-            REG_MASK | ADDR_MASK | ADDR_INC_MASK,
-            REG_MASK | ADDR_MASK | ADDR_INC_MASK | NUMBER_MASK,
-        ),
-        executor: two_operands_instruction!(|a, b| (a ^ b, false)),
-    },
-    InstructionInfo {
-        name: "not",
-        accepted_operands: AcceptedOperandTypes(REG_MASK | ADDR_MASK | ADDR_INC_MASK, 0),
-        executor: one_operand_instruction!(|a: u16| (!a, false)),
-    },
-    InstructionInfo {
-        name: "shl",
-        accepted_operands: AcceptedOperandTypes(
-            REG_MASK | ADDR_MASK | ADDR_INC_MASK,
-            REG_MASK | ADDR_MASK | ADDR_INC_MASK | NUMBER_MASK,
-        ),
-        executor: two_operands_instruction!(|a: u16, b: u16| a.overflowing_shl(b as u32)),
-    },
-    InstructionInfo {
-        name: "shr",
-        accepted_operands: AcceptedOperandTypes(
-            REG_MASK | ADDR_MASK | ADDR_INC_MASK,
-            REG_MASK | ADDR_MASK | ADDR_INC_MASK | NUMBER_MASK,
-        ),
-        executor: two_operands_instruction!(|a: u16, b: u16| a.overflowing_shr(b as u32)),
-    },
-    InstructionInfo {
-        name: "rol",
-        accepted_operands: AcceptedOperandTypes(
-            REG_MASK | ADDR_MASK | ADDR_INC_MASK,
-            REG_MASK | ADDR_MASK | ADDR_INC_MASK | NUMBER_MASK,
-        ),
-        executor: two_operands_instruction!(|a: u16, b: u16| (a.rotate_left(b as u32), false)),
-    },
-    InstructionInfo {
-        name: "ror",
-        accepted_operands: AcceptedOperandTypes(
-            REG_MASK | ADDR_MASK | ADDR_INC_MASK,
-            REG_MASK | ADDR_MASK | ADDR_INC_MASK | NUMBER_MASK,
-        ),
-        executor: two_operands_instruction!(|a: u16, b: u16| (a.rotate_right(b as u32), false)),
-    },
-    // InstructionInfo {
-    //     name: "cmp",
-    //     accepted_operands: AcceptedOperandTypes(
-    //         REG_MASK | ADDR_MASK | ADDR_INC_MASK,
-    //         REG_MASK | ADDR_MASK | ADDR_INC_MASK | NUMBER_MASK,
-    //     ),
-    //     executor: |executor, operands| {
-    //         let (op, size) = operands.two();
-    //
-    //         executor.add_to_pc(size);
-    //         Ok(())
-    //     },
-    // },
-    InstructionInfo {
-        name: "jmp",
-        accepted_operands: AcceptedOperandTypes(
-            REG_MASK | ADDR_MASK | ADDR_INC_MASK | NUMBER_MASK,
-            0,
-        ),
-        executor: |executor, operands| {
-            let (op, _) = operands.one();
-            let addr = executor.read_from(op)? as usize;
-            if addr >= MAX_PROGRAM_SIZE {
-                return Err(RuntimeError::InvalidAddress {
+        }
+    };
+}
+
+fn add_with_carry(a: u16, b: u16, carry: bool) -> (u16, bool) {
+    let (res, carry1) = a.overflowing_add(b);
+    let (res, carry2) = res.overflowing_add(carry as u16);
+    (res, carry1 || carry2)
+}
+
+fn sub_with_borrow(a: u16, b: u16, borrow: bool) -> (u16, bool) {
+    let (res, borrow1) = a.overflowing_sub(b);
+    let (res, borrow2) = res.overflowing_sub(borrow as u16);
+    (res, borrow1 || borrow2)
+}
+
+// Rotates the 17-bit value made of the carry and `a` by `n` bits.
+fn rotate_left_through_carry(a: u16, n: u16, carry: bool) -> (u16, bool) {
+    let mut res = (a, carry);
+    for _ in 0..(n % 17) {
+        res = ((res.0 << 1) | res.1 as u16, res.0 >> 15 == 1);
+    }
+    res
+}
+
+fn rotate_right_through_carry(a: u16, n: u16, carry: bool) -> (u16, bool) {
+    let mut res = (a, carry);
+    for _ in 0..(n % 17) {
+        res = ((res.0 >> 1) | ((res.1 as u16) << 15), res.0 & 1 == 1);
+    }
+    res
+}
+
+// Like `two_operands_instruction`, but `$f` is never called with a zero divisor.
+// Division by zero gives 0 with overflow, or an error in the strict division mode.
+macro_rules! division_instruction {
+    ($f:expr) => {
+        |executor: &mut ProgramExecutor, operands: InstructionOperands| {
+            let (op1, op2, size) = operands.two();
+            let b = executor.read_from(op2)?;
+            let a = executor.read_destination(op1)?;
+            let (res, overflow) = if b != 0 {
+                ($f)(a, b)
+            } else if executor.strict_division {
+                return Err(RuntimeError::DivisionByZero {
                     err_address: executor.curr_addr,
-                    address: addr,
                 });
-            }
-            executor.curr_addr = addr;
+            } else {
+                (0, true)
+            };
+            executor.write_to(op1, res)?;
+            executor.set_overflow(overflow);
+            executor.add_to_pc(size);
             Ok(())
-        },
-    },
-    InstructionInfo {
-        name: "wrt",
-        accepted_operands: AcceptedOperandTypes(
-            PORT_MASK,
-            REG_MASK | ADDR_MASK | ADDR_INC_MASK | NUMBER_MASK,
-        ),
-        executor: two_operands_instruction!(|_a, b| (b, false)),
-    },
-    InstructionInfo {
-        name: "read",
-        accepted_operands: AcceptedOperandTypes(REG_MASK | ADDR_MASK | ADDR_INC_MASK, PORT_MASK),
-        executor: two_operands_instruction!(|_a, b| (b, false)),
-    },
-    InstructionInfo {
-        name: "stop",
-        accepted_operands: AcceptedOperandTypes(0, 0),
-        executor: |executor, _operands| {
-            executor.has_finished = true;
+        }
+    };
+}
+
+// Reinterprets both operands as signed numbers.
+macro_rules! signed {
+    ($f:expr) => {
+        |a: u16, b: u16| {
+            let (res, overflow) = ($f)(a as i16, b as i16);
+            (res as u16, overflow)
+        }
+    };
+}
+
+// Changes bit number `op2 % 16` of `op1` with `$f(value, bit_mask)`.
+// The previous value of the bit goes to the carry flag.
+macro_rules! bit_instruction {
+    ($f:expr) => {
+        |executor: &mut ProgramExecutor, operands: InstructionOperands| {
+            let (op1, op2, size) = operands.two();
+            let mask = 1 << (executor.read_from(op2)? & 0xF);
+            let value = executor.read_destination(op1)?;
+            executor.write_to(op1, ($f)(value, mask))?;
+            executor.set_carry(value & mask != 0);
+            executor.set_overflow(false);
+            executor.add_to_pc(size);
             Ok(())
+        }
+    };
+}
+
+// Copies bit number `op2 % 16` of `op1` to the carry flag.
+fn bit_test(executor: &mut ProgramExecutor, operands: InstructionOperands) -> RuntimeResult<()> {
+    let (op1, op2, size) = operands.two();
+    let mask = 1 << (executor.read_from(op2)? & 0xF);
+    let value = executor.read_from(op1)?;
+    executor.set_carry(value & mask != 0);
+    executor.set_overflow(false);
+    executor.add_to_pc(size);
+    Ok(())
+}
+
+// Copies `op2` to `op1`. The old value of `op1` isn't read, so it doesn't appear
+// in the trace or trigger read watchpoints.
+fn move_value(executor: &mut ProgramExecutor, operands: InstructionOperands) -> RuntimeResult<()> {
+    let (op1, op2, size) = operands.two();
+    let value = executor.read_from(op2)?;
+    executor.write_to(op1, value)?;
+    executor.set_overflow(false);
+    executor.add_to_pc(size);
+    Ok(())
+}
+
+// Byte instructions write only the low byte of a register by default.
+fn replace_low_byte(byte: u8, old: u16) -> u16 {
+    (old & 0xFF00) | byte as u16
+}
+
+// Byte variant of `move_value`, a register or a port gets `$extend(byte, old_value)`.
+macro_rules! move_byte_instruction {
+    ($extend:expr) => {
+        |executor: &mut ProgramExecutor, operands: InstructionOperands| {
+            let (op1, op2, size) = operands.two();
+            let byte = executor.read_u8_from(op2)?;
+            executor.write_u8_to(op1, byte, $extend)?;
+            executor.set_overflow(false);
+            executor.add_to_pc(size);
+            Ok(())
+        }
+    };
+}
+
+macro_rules! two_byte_operands_instruction {
+    ($f:expr) => {
+        two_byte_operands_instruction!($f, replace_low_byte)
+    };
+    ($f:expr, $extend:expr) => {
+        |executor: &mut ProgramExecutor, operands: InstructionOperands| {
+            let (op1, op2, size) = operands.two();
+            let b = executor.read_u8_from(op2)?;
+            let (res, overflow) = ($f)(executor.read_u8_destination(op1)?, b);
+            executor.write_u8_to(op1, res, $extend)?;
+            executor.set_overflow(overflow);
+            executor.add_to_pc(size);
+            Ok(())
+        }
+    };
+}
+
+// Programs for an older version of the instruction set still run, as opcodes are never
+// reused. `FIRST_COMPATIBLE_ISA_VERSION` is raised only if an opcode changes its meaning.
+pub const ISA_VERSION: u16 = 1;
+pub const FIRST_COMPATIBLE_ISA_VERSION: u16 = 1;
+
+// Opcodes are explicit, so that adding an instruction anywhere in the list doesn't change
+// the opcodes of the others and programs assembled earlier keep working:
+// 0x00..=0x11 - the original set, 0x12..=0x18 - byte instructions,
+// 0x19..=0x1D - multiplication and division, 0x1E..=0x21 - carry instructions,
+// 0x22..=0x26 - single-purpose instructions, 0x27..=0x2E - bit instructions.
+// A new instruction takes the next free opcode and raises `ISA_VERSION`.
+pub const INSTRUCTION_SET: [(u8, InstructionInfo); 47] = [
+    (
+        0x00,
+        InstructionInfo {
+            name: "nop",
+            accepted_operands: AcceptedOperandTypes(0, 0),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: |executor, operands| {
+                let size = operands.zero();
+                executor.add_to_pc(size);
+                Ok(())
+            },
+        },
+    ),
+    (
+        0x01,
+        InstructionInfo {
+            name: "mov",
+            accepted_operands: AcceptedOperandTypes(
+                REG_MASK | MEMORY_MASK,
+                REG_MASK | MEMORY_MASK | NUMBER_MASK,
+            ),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: move_value,
+        },
+    ),
+    (
+        0x02,
+        InstructionInfo {
+            name: "add",
+            accepted_operands: AcceptedOperandTypes(
+                REG_MASK | MEMORY_MASK,
+                REG_MASK | MEMORY_MASK | NUMBER_MASK,
+            ),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: carry_instruction!(|a: u16, b, _| a.overflowing_add(b)),
+        },
+    ),
+    (
+        0x03,
+        InstructionInfo {
+            name: "sub",
+            accepted_operands: AcceptedOperandTypes(
+                REG_MASK | MEMORY_MASK,
+                REG_MASK | MEMORY_MASK | NUMBER_MASK,
+            ),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: carry_instruction!(|a: u16, b, _| a.overflowing_sub(b)),
+        },
+    ),
+    (
+        0x04,
+        InstructionInfo {
+            name: "mul",
+            accepted_operands: AcceptedOperandTypes(
+                REG_MASK | MEMORY_MASK,
+                REG_MASK | MEMORY_MASK | NUMBER_MASK,
+            ),
+            cycles: 4,
+            requires_register_pair: false,
+            executor: two_operands_instruction!(u16::overflowing_mul),
+        },
+    ),
+    (
+        0x05,
+        InstructionInfo {
+            name: "div",
+            accepted_operands: AcceptedOperandTypes(
+                REG_MASK | MEMORY_MASK,
+                REG_MASK | MEMORY_MASK | NUMBER_MASK,
+            ),
+            cycles: 8,
+            requires_register_pair: false,
+            executor: division_instruction!(u16::overflowing_div),
+        },
+    ),
+    (
+        0x06,
+        InstructionInfo {
+            name: "and",
+            accepted_operands: AcceptedOperandTypes(
+                REG_MASK | MEMORY_MASK,
+                REG_MASK | MEMORY_MASK | NUMBER_MASK,
+            ),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: two_operands_instruction!(|a, b| (a & b, false)),
+        },
+    ),
+    (
+        0x07,
+        InstructionInfo {
+            name: "or",
+            accepted_operands: AcceptedOperandTypes(
+                REG_MASK | MEMORY_MASK,
+                REG_MASK | MEMORY_MASK | NUMBER_MASK,
+            ),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: two_operands_instruction!(|a, b| (a | b, false)),
+        },
+    ),
+    (
+        0x08,
+        InstructionInfo {
+            name: "xor",
+            accepted_operands: AcceptedOperandTypes(
+                REG_MASK | MEMORY_MASK,
+                REG_MASK | MEMORY_MASK | NUMBER_MASK,
+            ),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: two_operands_instruction!(|a, b| (a ^ b, false)),
+        },
+    ),
+    (
+        0x09,
+        InstructionInfo {
+            name: "not",
+            accepted_operands: AcceptedOperandTypes(REG_MASK | MEMORY_MASK, 0),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: one_operand_instruction!(|a: u16| (!a, false)),
+        },
+    ),
+    (
+        0x0A,
+        InstructionInfo {
+            name: "shl",
+            accepted_operands: AcceptedOperandTypes(
+                REG_MASK | MEMORY_MASK,
+                REG_MASK | MEMORY_MASK | NUMBER_MASK,
+            ),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: two_operands_instruction!(|a: u16, b: u16| a.overflowing_shl(b as u32)),
+        },
+    ),
+    (
+        0x0B,
+        InstructionInfo {
+            name: "shr",
+            accepted_operands: AcceptedOperandTypes(
+                REG_MASK | MEMORY_MASK,
+                REG_MASK | MEMORY_MASK | NUMBER_MASK,
+            ),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: two_operands_instruction!(|a: u16, b: u16| a.overflowing_shr(b as u32)),
+        },
+    ),
+    (
+        0x0C,
+        InstructionInfo {
+            name: "rol",
+            accepted_operands: AcceptedOperandTypes(
+                REG_MASK | MEMORY_MASK,
+                REG_MASK | MEMORY_MASK | NUMBER_MASK,
+            ),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: two_operands_instruction!(|a: u16, b: u16| (a.rotate_left(b as u32), false)),
+        },
+    ),
+    (
+        0x0D,
+        InstructionInfo {
+            name: "ror",
+            accepted_operands: AcceptedOperandTypes(
+                REG_MASK | MEMORY_MASK,
+                REG_MASK | MEMORY_MASK | NUMBER_MASK,
+            ),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: two_operands_instruction!(|a: u16, b: u16| (a.rotate_right(b as u32), false)),
+        },
+    ),
+    // (
+    //     0x2F,
+    //     InstructionInfo {
+    //         name: "cmp",
+    //         accepted_operands: AcceptedOperandTypes(
+    //             REG_MASK | MEMORY_MASK,
+    //             REG_MASK | MEMORY_MASK | NUMBER_MASK,
+    //         ),
+    //         cycles: 1,
+    //         requires_register_pair: false,
+    //         executor: |executor, operands| {
+    //             let (op, size) = operands.two();
+    //
+    //             executor.add_to_pc(size);
+    //             Ok(())
+    //         },
+    //     },
+    // ),
+    (
+        0x0E,
+        InstructionInfo {
+            name: "jmp",
+            accepted_operands: AcceptedOperandTypes(REG_MASK | MEMORY_MASK | NUMBER_MASK, 0),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: |executor, operands| {
+                let (op, _) = operands.one();
+                let addr = executor.read_from(op)? as usize;
+                if addr >= executor.config().memory_size {
+                    return Err(RuntimeError::InvalidAddress {
+                        err_address: executor.curr_addr,
+                        address: addr,
+                    });
+                }
+                executor.curr_addr = addr;
+                Ok(())
+            },
+        },
+    ),
+    (
+        0x0F,
+        InstructionInfo {
+            name: "wrt",
+            accepted_operands: AcceptedOperandTypes(
+                PORT_MASK,
+                REG_MASK | MEMORY_MASK | NUMBER_MASK,
+            ),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: move_value,
+        },
+    ),
+    (
+        0x10,
+        InstructionInfo {
+            name: "read",
+            accepted_operands: AcceptedOperandTypes(REG_MASK | MEMORY_MASK, PORT_MASK),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: move_value,
+        },
+    ),
+    (
+        0x11,
+        InstructionInfo {
+            name: "stop",
+            accepted_operands: AcceptedOperandTypes(0, 0),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: |executor, _operands| {
+                executor.has_finished = true;
+                Ok(())
+            },
+        },
+    ),
+    // Sign-extends the byte when the destination is a register.
+    (
+        0x12,
+        InstructionInfo {
+            name: "movb",
+            accepted_operands: AcceptedOperandTypes(
+                REG_MASK | MEMORY_MASK,
+                REG_MASK | MEMORY_MASK | NUMBER_MASK,
+            ),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: move_byte_instruction!(|byte: u8, _| byte as i8 as u16),
+        },
+    ),
+    // Zero-extends the byte when the destination is a register.
+    (
+        0x13,
+        InstructionInfo {
+            name: "movzb",
+            accepted_operands: AcceptedOperandTypes(
+                REG_MASK | MEMORY_MASK,
+                REG_MASK | MEMORY_MASK | NUMBER_MASK,
+            ),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: move_byte_instruction!(|byte: u8, _| byte as u16),
+        },
+    ),
+    (
+        0x14,
+        InstructionInfo {
+            name: "addb",
+            accepted_operands: AcceptedOperandTypes(
+                REG_MASK | MEMORY_MASK,
+                REG_MASK | MEMORY_MASK | NUMBER_MASK,
+            ),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: two_byte_operands_instruction!(u8::overflowing_add),
+        },
+    ),
+    (
+        0x15,
+        InstructionInfo {
+            name: "subb",
+            accepted_operands: AcceptedOperandTypes(
+                REG_MASK | MEMORY_MASK,
+                REG_MASK | MEMORY_MASK | NUMBER_MASK,
+            ),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: two_byte_operands_instruction!(u8::overflowing_sub),
+        },
+    ),
+    (
+        0x16,
+        InstructionInfo {
+            name: "andb",
+            accepted_operands: AcceptedOperandTypes(
+                REG_MASK | MEMORY_MASK,
+                REG_MASK | MEMORY_MASK | NUMBER_MASK,
+            ),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: two_byte_operands_instruction!(|a, b| (a & b, false)),
+        },
+    ),
+    (
+        0x17,
+        InstructionInfo {
+            name: "orb",
+            accepted_operands: AcceptedOperandTypes(
+                REG_MASK | MEMORY_MASK,
+                REG_MASK | MEMORY_MASK | NUMBER_MASK,
+            ),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: two_byte_operands_instruction!(|a, b| (a | b, false)),
+        },
+    ),
+    (
+        0x18,
+        InstructionInfo {
+            name: "xorb",
+            accepted_operands: AcceptedOperandTypes(
+                REG_MASK | MEMORY_MASK,
+                REG_MASK | MEMORY_MASK | NUMBER_MASK,
+            ),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: two_byte_operands_instruction!(|a, b| (a ^ b, false)),
+        },
+    ),
+    (
+        0x19,
+        InstructionInfo {
+            name: "imul",
+            accepted_operands: AcceptedOperandTypes(
+                REG_MASK | MEMORY_MASK,
+                REG_MASK | MEMORY_MASK | NUMBER_MASK,
+            ),
+            cycles: 4,
+            requires_register_pair: false,
+            executor: two_operands_instruction!(signed!(i16::overflowing_mul)),
+        },
+    ),
+    (
+        0x1A,
+        InstructionInfo {
+            name: "idiv",
+            accepted_operands: AcceptedOperandTypes(
+                REG_MASK | MEMORY_MASK,
+                REG_MASK | MEMORY_MASK | NUMBER_MASK,
+            ),
+            cycles: 8,
+            requires_register_pair: false,
+            executor: division_instruction!(signed!(i16::overflowing_div)),
+        },
+    ),
+    (
+        0x1B,
+        InstructionInfo {
+            name: "mod",
+            accepted_operands: AcceptedOperandTypes(
+                REG_MASK | MEMORY_MASK,
+                REG_MASK | MEMORY_MASK | NUMBER_MASK,
+            ),
+            cycles: 8,
+            requires_register_pair: false,
+            executor: division_instruction!(u16::overflowing_rem),
+        },
+    ),
+    (
+        0x1C,
+        InstructionInfo {
+            name: "imod",
+            accepted_operands: AcceptedOperandTypes(
+                REG_MASK | MEMORY_MASK,
+                REG_MASK | MEMORY_MASK | NUMBER_MASK,
+            ),
+            cycles: 8,
+            requires_register_pair: false,
+            executor: division_instruction!(signed!(i16::overflowing_rem)),
+        },
+    ),
+    // Unsigned 32-bit product of `Rn` and the second operand: the high word goes to `Rn`,
+    // the low word to `Rn+1`. Overflow is set when the high word isn't zero.
+    (
+        0x1D,
+        InstructionInfo {
+            name: "mulw",
+            accepted_operands: AcceptedOperandTypes(REG_MASK, REG_MASK | MEMORY_MASK | NUMBER_MASK),
+            cycles: 5,
+            requires_register_pair: true,
+            executor: |executor, operands| {
+                let (op1, op2, size) = operands.two();
+                let reg = match op1 {
+                    InstructionOperand::Reg(reg) => reg,
+                    _ => {
+                        return Err(RuntimeError::InvalidOperand {
+                            err_address: executor.curr_addr,
+                            operand: executor
+                                .read_u8((executor.curr_addr as u16).wrapping_add(1))?
+                                >> 4,
+                        })
+                    }
+                };
+                let b = executor.read_from(op2)?;
+                let product = executor.read_from(op1)? as u32 * b as u32;
+                executor.write_to(op1, (product >> 16) as u16)?;
+                executor.write_to(InstructionOperand::Reg(reg + 1), product as u16)?;
+                executor.set_overflow(product > u16::MAX as u32);
+                executor.add_to_pc(size);
+                Ok(())
+            },
+        },
+    ),
+    (
+        0x1E,
+        InstructionInfo {
+            name: "adc",
+            accepted_operands: AcceptedOperandTypes(
+                REG_MASK | MEMORY_MASK,
+                REG_MASK | MEMORY_MASK | NUMBER_MASK,
+            ),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: carry_instruction!(add_with_carry),
+        },
+    ),
+    (
+        0x1F,
+        InstructionInfo {
+            name: "sbc",
+            accepted_operands: AcceptedOperandTypes(
+                REG_MASK | MEMORY_MASK,
+                REG_MASK | MEMORY_MASK | NUMBER_MASK,
+            ),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: carry_instruction!(sub_with_borrow),
+        },
+    ),
+    (
+        0x20,
+        InstructionInfo {
+            name: "rcl",
+            accepted_operands: AcceptedOperandTypes(
+                REG_MASK | MEMORY_MASK,
+                REG_MASK | MEMORY_MASK | NUMBER_MASK,
+            ),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: carry_instruction!(rotate_left_through_carry),
+        },
+    ),
+    (
+        0x21,
+        InstructionInfo {
+            name: "rcr",
+            accepted_operands: AcceptedOperandTypes(
+                REG_MASK | MEMORY_MASK,
+                REG_MASK | MEMORY_MASK | NUMBER_MASK,
+            ),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: carry_instruction!(rotate_right_through_carry),
+        },
+    ),
+    (
+        0x22,
+        InstructionInfo {
+            name: "inc",
+            accepted_operands: AcceptedOperandTypes(REG_MASK | MEMORY_MASK, 0),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: one_operand_instruction!(|a: u16| a.overflowing_add(1)),
+        },
+    ),
+    (
+        0x23,
+        InstructionInfo {
+            name: "dec",
+            accepted_operands: AcceptedOperandTypes(REG_MASK | MEMORY_MASK, 0),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: one_operand_instruction!(|a: u16| a.overflowing_sub(1)),
+        },
+    ),
+    // Overflow is set only for -32768, which has no positive counterpart.
+    (
+        0x24,
+        InstructionInfo {
+            name: "neg",
+            accepted_operands: AcceptedOperandTypes(REG_MASK | MEMORY_MASK, 0),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: one_operand_instruction!(|a: u16| {
+                let (res, overflow) = (a as i16).overflowing_neg();
+                (res as u16, overflow)
+            }),
+        },
+    ),
+    (
+        0x25,
+        InstructionInfo {
+            name: "swab",
+            accepted_operands: AcceptedOperandTypes(REG_MASK | MEMORY_MASK, 0),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: one_operand_instruction!(|a: u16| (a.swap_bytes(), false)),
+        },
+    ),
+    (
+        0x26,
+        InstructionInfo {
+            name: "xchg",
+            accepted_operands: AcceptedOperandTypes(REG_MASK | MEMORY_MASK, REG_MASK | MEMORY_MASK),
+            cycles: 2,
+            requires_register_pair: false,
+            executor: |executor, operands| {
+                let (op1, op2, size) = operands.two();
+                let a = executor.read_destination(op1)?;
+                let b = executor.read_destination(op2)?;
+                executor.write_to(op1, b)?;
+                executor.write_to(op2, a)?;
+                executor.set_overflow(false);
+                executor.add_to_pc(size);
+                Ok(())
+            },
+        },
+    ),
+    (
+        0x27,
+        InstructionInfo {
+            name: "bt",
+            accepted_operands: AcceptedOperandTypes(REG_MASK | MEMORY_MASK, REG_MASK | NUMBER_MASK),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: bit_test,
+        },
+    ),
+    (
+        0x28,
+        InstructionInfo {
+            name: "bts",
+            accepted_operands: AcceptedOperandTypes(REG_MASK | MEMORY_MASK, REG_MASK | NUMBER_MASK),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: bit_instruction!(|value, mask| value | mask),
+        },
+    ),
+    (
+        0x29,
+        InstructionInfo {
+            name: "btr",
+            accepted_operands: AcceptedOperandTypes(REG_MASK | MEMORY_MASK, REG_MASK | NUMBER_MASK),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: bit_instruction!(|value, mask: u16| value & !mask),
+        },
+    ),
+    (
+        0x2A,
+        InstructionInfo {
+            name: "btc",
+            accepted_operands: AcceptedOperandTypes(REG_MASK | MEMORY_MASK, REG_MASK | NUMBER_MASK),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: bit_instruction!(|value, mask| value ^ mask),
         },
-    },
+    ),
+    // The same bit instructions for ports: `bt p3, 5` tests a switch,
+    // `bts p3, 5` lights a single lamp.
+    (
+        0x2B,
+        InstructionInfo {
+            name: "bt",
+            accepted_operands: AcceptedOperandTypes(PORT_MASK, REG_MASK | NUMBER_MASK),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: bit_test,
+        },
+    ),
+    (
+        0x2C,
+        InstructionInfo {
+            name: "bts",
+            accepted_operands: AcceptedOperandTypes(PORT_MASK, REG_MASK | NUMBER_MASK),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: bit_instruction!(|value, mask| value | mask),
+        },
+    ),
+    (
+        0x2D,
+        InstructionInfo {
+            name: "btr",
+            accepted_operands: AcceptedOperandTypes(PORT_MASK, REG_MASK | NUMBER_MASK),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: bit_instruction!(|value, mask: u16| value & !mask),
+        },
+    ),
+    (
+        0x2E,
+        InstructionInfo {
+            name: "btc",
+            accepted_operands: AcceptedOperandTypes(PORT_MASK, REG_MASK | NUMBER_MASK),
+            cycles: 1,
+            requires_register_pair: false,
+            executor: bit_instruction!(|value, mask| value ^ mask),
+        },
+    ),
 ];
+
+// Duplicate opcodes or opcodes that clash with `EXTENDED_OPERANDS_FLAG` fail the build.
+const _: () = assert!(has_valid_opcodes(&INSTRUCTION_SET));
+
+const fn has_valid_opcodes(instructions: &[(u8, InstructionInfo)]) -> bool {
+    let mut i = 0;
+    while i < instructions.len() {
+        if instructions[i].0 & EXTENDED_OPERANDS_FLAG != 0 {
+            return false;
+        }
+        let mut j = i + 1;
+        while j < instructions.len() {
+            if instructions[i].0 == instructions[j].0 {
+                return false;
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    true
+}
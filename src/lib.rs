@@ -0,0 +1,17 @@
+// The assembler, the emulator and the editor parts of the panel, so that other crates can
+// build a `Compiler` and a `ProgramExecutor` with their own instructions.
+
+pub mod breakpoints;
+pub mod cli;
+pub mod compiler;
+pub mod devices;
+pub mod executor;
+mod fuzz;
+pub mod highlighting;
+pub mod instruction_registry;
+pub mod instruction_set;
+mod loop_detection;
+pub mod machine;
+pub mod snapshot;
+pub mod trace;
+pub mod watchpoints;
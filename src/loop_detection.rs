@@ -0,0 +1,71 @@
+// Detection of programs that provably run forever.
+//
+// If no memory or lamp changed, the next state of the machine depends only on registers, flags,
+// PC and the input devices. So once that state repeats, the program is stuck in a loop.
+// A loop that keeps changing the lamps is an animation, not a bug, and a loop that polls
+// a switch nobody toggles waits forever. Cycles of any length are found by comparing the state with
+// a checkpoint that is moved at doubling intervals (Brent's algorithm).
+
+// This many `0x0000` instructions (`nop` with no operands) in a row mean that the program
+// ran out of its code into zeroed memory.
+pub const MAX_ZERO_INSTRUCTIONS_IN_A_ROW: u32 = 32;
+
+#[derive(Clone, PartialEq)]
+pub struct MachineState {
+    pub registers: [u16; 5],
+    pub program_state_reg: u16,
+    pub curr_addr: usize,
+    // Reading an unchanged switch or key gives the same value again.
+    pub input_changes: u64,
+}
+
+#[derive(Default)]
+pub struct LoopDetector {
+    checkpoint: Option<MachineState>,
+    steps_since_checkpoint: u64,
+    checkpoint_interval: u64,
+    had_side_effects: bool,
+    zero_instructions_in_a_row: u32,
+}
+
+impl LoopDetector {
+    // Memory or a lamp changed, so earlier states prove nothing.
+    pub fn side_effect(&mut self) {
+        self.had_side_effects = true;
+    }
+
+    // Called before every instruction. Returns the number of `0x0000` instructions
+    // executed in a row, including this one.
+    pub fn zero_instruction(&mut self, is_zero: bool) -> u32 {
+        if is_zero {
+            self.zero_instructions_in_a_row += 1;
+        } else {
+            self.zero_instructions_in_a_row = 0;
+        }
+        self.zero_instructions_in_a_row
+    }
+
+    // Called after every instruction. Returns true if `state` has already been seen
+    // with no side effects since then.
+    pub fn is_repeated(&mut self, state: MachineState) -> bool {
+        if self.had_side_effects || self.checkpoint.is_none() {
+            self.had_side_effects = false;
+            self.move_checkpoint(state, 1);
+            return false;
+        }
+        if self.checkpoint.as_ref() == Some(&state) {
+            return true;
+        }
+        self.steps_since_checkpoint += 1;
+        if self.steps_since_checkpoint >= self.checkpoint_interval {
+            self.move_checkpoint(state, self.checkpoint_interval * 2);
+        }
+        false
+    }
+
+    fn move_checkpoint(&mut self, state: MachineState, interval: u64) {
+        self.checkpoint = Some(state);
+        self.steps_since_checkpoint = 0;
+        self.checkpoint_interval = interval;
+    }
+}
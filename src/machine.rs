@@ -0,0 +1,160 @@
+// Size of the emulated machine, read by the compiler, the executor and the UI.
+//
+// The encoding of instructions limits it: addresses are words, so memory takes at most
+// the whole 64 KiB address space; operand codes have room for 4 general registers and SP;
+// port numbers are bytes. The switches and the keyboard need the first 16 ports.
+
+use crate::instruction_set::InstructionOperand;
+use std::fmt::{Display, Formatter};
+
+pub const MAX_MEMORY_SIZE: usize = 0x10000;
+pub const MIN_MEMORY_SIZE: usize = 0x100;
+pub const MIN_PORTS: usize = 16;
+pub const MAX_PORTS: usize = 256;
+pub const MAX_GENERAL_REGISTERS: usize = 4;
+// Index of SP in `ProgramExecutor::registers` and its operand code.
+pub const SP: u8 = 4;
+// Every port drives a row of 16 lamps, a wider display puts several ports in a row.
+pub const LAMPS_PER_PORT: usize = 16;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct MachineConfig {
+    pub memory_size: usize,
+    // Lamps in a row, a multiple of `LAMPS_PER_PORT`.
+    pub display_width: usize,
+    pub display_height: usize,
+    // R0..R(n-1), SP is always there.
+    pub general_registers: usize,
+}
+
+impl Default for MachineConfig {
+    fn default() -> Self {
+        PRESETS[0].1
+    }
+}
+
+pub const PRESETS: [(&str, MachineConfig); 4] = [
+    (
+        "classic",
+        MachineConfig {
+            memory_size: 0x1000,
+            display_width: 16,
+            display_height: 16,
+            general_registers: 4,
+        },
+    ),
+    (
+        "large",
+        MachineConfig {
+            memory_size: MAX_MEMORY_SIZE,
+            display_width: 32,
+            display_height: 32,
+            general_registers: 4,
+        },
+    ),
+    (
+        "wide",
+        MachineConfig {
+            memory_size: 0x4000,
+            display_width: 64,
+            display_height: 16,
+            general_registers: 4,
+        },
+    ),
+    (
+        "minimal",
+        MachineConfig {
+            memory_size: 0x400,
+            display_width: 16,
+            display_height: 16,
+            general_registers: 2,
+        },
+    ),
+];
+
+impl MachineConfig {
+    pub fn preset(name: &str) -> Option<Self> {
+        PRESETS
+            .iter()
+            .find(|(preset, _)| preset.eq_ignore_ascii_case(name))
+            .map(|&(_, config)| config)
+    }
+
+    pub fn ports_per_row(&self) -> usize {
+        self.display_width / LAMPS_PER_PORT
+    }
+
+    pub fn ports(&self) -> usize {
+        self.ports_per_row() * self.display_height
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !(MIN_MEMORY_SIZE..=MAX_MEMORY_SIZE).contains(&self.memory_size) {
+            return Err(format!(
+                "Memory size must be from {MIN_MEMORY_SIZE} to {MAX_MEMORY_SIZE} bytes"
+            ));
+        }
+        if self.display_width == 0 || !self.display_width.is_multiple_of(LAMPS_PER_PORT) {
+            return Err(format!(
+                "Display width must be a multiple of {LAMPS_PER_PORT}"
+            ));
+        }
+        if !(MIN_PORTS..=MAX_PORTS).contains(&self.ports()) {
+            return Err(format!(
+                "The display must take from {MIN_PORTS} to {MAX_PORTS} ports"
+            ));
+        }
+        if !(1..=MAX_GENERAL_REGISTERS).contains(&self.general_registers) {
+            return Err(format!(
+                "There must be from 1 to {MAX_GENERAL_REGISTERS} general registers"
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn has_register(&self, reg: u8) -> bool {
+        reg == SP || (reg as usize) < self.general_registers
+    }
+
+    // `mulw Rn, ..` also writes `Rn+1`, which must be a general register too.
+    pub fn has_register_pair(&self, reg: u8) -> bool {
+        (reg as usize + 1) < self.general_registers
+    }
+
+    // Whether the registers and the port of an operand exist on this machine.
+    pub fn has_operand(&self, operand: InstructionOperand) -> bool {
+        match operand {
+            InstructionOperand::Reg(reg)
+            | InstructionOperand::Addr(reg)
+            | InstructionOperand::AddrInc(reg)
+            | InstructionOperand::AddrDec(reg)
+            | InstructionOperand::Indexed(reg, _) => self.has_register(reg),
+            InstructionOperand::Port(port) => (port as usize) < self.ports(),
+            InstructionOperand::Absolute(_) | InstructionOperand::Number(_) => true,
+        }
+    }
+
+    // `r0`.. and `sp`.
+    pub fn register_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = (0..self.general_registers)
+            .map(|i| format!("r{i}"))
+            .collect();
+        names.push("sp".to_string());
+        names
+    }
+}
+
+impl Display for MachineConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.memory_size.is_multiple_of(1024) {
+            write!(f, "{} KiB", self.memory_size / 1024)?;
+        } else {
+            write!(f, "{} bytes", self.memory_size)?;
+        }
+        write!(
+            f,
+            ", {}×{} lamps, {} registers",
+            self.display_width, self.display_height, self.general_registers
+        )
+    }
+}
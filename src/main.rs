@@ -1,21 +1,28 @@
 // hide console window on Windows in release
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-pub mod compiler;
-mod executor;
-mod highlighting;
-pub mod instruction_set;
-
-use crate::compiler::{CompilationError, Compiler, ErrorsHighlightInfo, MAX_PROGRAM_SIZE};
-use crate::executor::{ProgramExecutor, RuntimeError};
-use crate::highlighting::{highlight, CodeTheme, TokenType};
 use eframe::egui;
-use eframe::egui::{include_image, vec2, Align2, Color32, RichText, Vec2, Visuals, Widget};
+use eframe::egui::{
+    include_image, vec2, Align2, Color32, RichText, Stroke, TextFormat, Vec2, Visuals, Widget,
+};
 use eframe::epaint::text::LayoutJob;
-use eframe::wgpu::rwh::HasWindowHandle;
+use rustanel::breakpoints::Breakpoint;
+use rustanel::cli;
+use rustanel::compiler::{CompilationError, Compiler, ErrorsHighlightInfo};
+use rustanel::devices::{key_code, KEY_CODE_PORT, KEY_STATE_PORT};
+use rustanel::executor::{CodeProtection, ProgramExecutor, RuntimeError, StackRegion};
+use rustanel::highlighting::{highlight, wrapping_parse, CodeTheme, TokenType};
+use rustanel::machine::{MachineConfig, LAMPS_PER_PORT, PRESETS, SP};
+use rustanel::snapshot::Snapshot;
+use rustanel::trace::{parse_address_range, Tracer};
+use rustanel::watchpoints::{WatchKind, WatchTarget, Watchpoint};
 use std::ops::Range;
 
 fn main() -> Result<(), eframe::Error> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        std::process::exit(cli::run(&args));
+    }
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
             .with_app_id("rustanel")
@@ -31,9 +38,24 @@ fn main() -> Result<(), eframe::Error> {
     )
 }
 
+// Example programs that can be opened from the settings panel.
+const EXAMPLES: [(&str, &str); 4] = [
+    (
+        "32-bit counter",
+        include_str!("../data/examples/counter32.asm"),
+    ),
+    (
+        "32-bit running light",
+        include_str!("../data/examples/running_light32.asm"),
+    ),
+    ("32-bit sum", include_str!("../data/examples/sum32.asm")),
+    ("Keyboard", include_str!("../data/examples/keyboard.asm")),
+];
+
 pub enum ErrorPopupInfo {
     CompilationError(CompilationError),
     RuntimeError(RuntimeError),
+    FileError(String),
     None,
 }
 
@@ -46,6 +68,14 @@ struct App {
     ticks_per_second: f32,
     last_instruction_time: f32,
     start_time: web_time::Instant,
+    new_watchpoint_target: String,
+    new_watchpoint_kind: WatchKind,
+    new_breakpoint_address: String,
+    new_breakpoint_condition: String,
+    snapshot_path: String,
+    quick_save_slots: [Option<Snapshot>; 3],
+    trace_ranges: String,
+    trace_path: String,
 }
 
 impl Default for App {
@@ -75,6 +105,14 @@ impl Default for App {
             ticks_per_second: 10.0,
             last_instruction_time: 0.0,
             start_time: web_time::Instant::now(),
+            new_watchpoint_target: String::new(),
+            new_watchpoint_kind: WatchKind::Write,
+            new_breakpoint_address: String::new(),
+            new_breakpoint_condition: String::new(),
+            snapshot_path: "machine.snap".into(),
+            quick_save_slots: Default::default(),
+            trace_ranges: String::new(),
+            trace_path: "trace.jsonl".into(),
         }
     }
 }
@@ -85,24 +123,48 @@ impl App {
         ui: &mut egui::Ui,
         theme: &CodeTheme,
         errors: &ErrorsHighlightInfo,
+        keywords: &[&'static str],
     ) {
         theme.apply_bg_color(ui);
         let mut layouter = |ui: &egui::Ui, string: &dyn egui::TextBuffer, wrap_width: f32| {
-            let mut layout_job = highlight(ui.ctx(), theme, string.as_str(), errors);
+            let mut layout_job = highlight(ui.ctx(), theme, string.as_str(), errors, keywords);
             layout_job.wrap.max_width = wrap_width;
             ui.fonts_mut(|f| f.layout_job(layout_job))
         };
         egui::ScrollArea::vertical().show(ui, |ui| {
-            ui.add(
+            let response = ui.add(
                 egui::TextEdit::multiline(&mut self.code)
                     .code_editor()
                     .desired_rows(1)
                     .desired_width(ui.available_width() * 0.5)
                     .layouter(&mut layouter),
             );
+            if errors.iter().any(|(_, err)| err.suggestion().is_some()) {
+                response.context_menu(|ui| self.quick_fix_buttons_ui(ui, errors));
+            }
         });
     }
 
+    // Shows a button for every error that has a suggestion. Clicking it replaces the
+    // erroneous token in the code with the suggested text.
+    fn quick_fix_buttons_ui(&mut self, ui: &mut egui::Ui, errors: &ErrorsHighlightInfo) {
+        for (range, err) in errors {
+            let (Some(suggestion), Some(original)) =
+                (err.suggestion(), self.code.get(range.clone()))
+            else {
+                continue;
+            };
+            if ui
+                .button(format!("Replace `{original}` with `{suggestion}`"))
+                .clicked()
+            {
+                self.code.replace_range(range.clone(), suggestion);
+                // The other ranges are stale now, they will be fixed after recompilation.
+                return;
+            }
+        }
+    }
+
     fn draw_lamp(&mut self, ui: &mut egui::Ui, lamp_size: f32, enabled: bool) -> egui::Response {
         let image = if enabled {
             if ui.visuals().dark_mode {
@@ -121,6 +183,63 @@ impl App {
         )
     }
 
+    // A toggle switch: the knob is up when the switch is on.
+    fn draw_switch(
+        &mut self,
+        ui: &mut egui::Ui,
+        size: f32,
+        on: bool,
+        clickable: bool,
+    ) -> egui::Response {
+        let sense = if clickable {
+            egui::Sense::click()
+        } else {
+            egui::Sense::hover()
+        };
+        let (rect, response) = ui.allocate_exact_size(Vec2::splat(size), sense);
+        let visuals = ui.style().interact(&response);
+        let body = rect.shrink2(vec2(size * 0.3, size * 0.1));
+        let knob = if on {
+            body.split_top_bottom_at_fraction(0.5).0
+        } else {
+            body.split_top_bottom_at_fraction(0.5).1
+        };
+        let painter = ui.painter();
+        painter.rect_filled(body, size * 0.1, ui.visuals().extreme_bg_color);
+        painter.rect_filled(
+            knob.shrink(size * 0.05),
+            size * 0.1,
+            if on {
+                Color32::from_rgb(230, 180, 40)
+            } else {
+                visuals.fg_stroke.color
+            },
+        );
+        response
+    }
+
+    // Passes key events to the keyboard device unless a text field is being edited.
+    fn handle_keyboard_input(&mut self, ctx: &egui::Context) {
+        if ctx.wants_keyboard_input() {
+            return;
+        }
+        ctx.input(|i| {
+            for event in &i.events {
+                let egui::Event::Key { key, pressed, .. } = event else {
+                    continue;
+                };
+                let Some(code) = key_code(key.name()) else {
+                    continue;
+                };
+                if *pressed {
+                    self.program_executor.keyboard.press(code);
+                } else {
+                    self.program_executor.keyboard.release(code);
+                }
+            }
+        });
+    }
+
     fn get_monospace(text: &str, size: f32) -> RichText {
         RichText::new(text).size(size).monospace()
     }
@@ -131,20 +250,54 @@ impl App {
             .min_col_width(0.0)
             .min_row_height(0.0)
             .show(ui, |ui| {
-                for i in 0..16 {
-                    let space = if i > 9 { "" } else { " " };
-                    ui.label(Self::get_monospace(
-                        &format!("{space}P{i}"),
+                let config = self.program_executor.config();
+                let ports_per_row = config.ports_per_row();
+                let label_width = format!("P{}", config.ports() - 1).len();
+                // Lamp or switch `j` of a row: its port and bit.
+                let lamp = |row: usize, j: usize| {
+                    (
+                        row * ports_per_row + j / LAMPS_PER_PORT,
+                        LAMPS_PER_PORT - 1 - j % LAMPS_PER_PORT,
+                    )
+                };
+                for row in 0..config.display_height {
+                    let ports = (row * ports_per_row)..((row + 1) * ports_per_row);
+                    let response = ui.label(Self::get_monospace(
+                        &format!("{:>label_width$}", format!("P{}", ports.start)),
                         lamp_size * 0.7,
                     ));
-                    for j in 0..16 {
+                    if ports.contains(&(KEY_STATE_PORT as usize)) {
+                        response
+                            .on_hover_text(format!("Input of P{KEY_STATE_PORT}: keys held down"));
+                    } else if ports.contains(&(KEY_CODE_PORT as usize)) {
+                        response.on_hover_text(format!(
+                            "Input of P{KEY_CODE_PORT}: the next pressed key"
+                        ));
+                    }
+                    for j in 0..config.display_width {
+                        let (port, bit) = lamp(row, j);
                         let response = self.draw_lamp(
                             ui,
                             lamp_size,
-                            (self.program_executor.display[i] >> (15 - j)) & 1 == 1,
+                            (self.program_executor.display[port] >> bit) & 1 == 1,
                         );
                         if response.clicked() {
-                            self.program_executor.display[i] ^= 1 << (15 - j);
+                            self.program_executor.display[port] ^= 1 << bit;
+                        }
+                    }
+                    ui.allocate_space(vec2(lamp_size * 0.5, 0.0));
+                    for j in 0..config.display_width {
+                        let (port, bit) = lamp(row, j);
+                        // The keyboard ports only show what the program would read.
+                        let keyboard_value = self.program_executor.keyboard.peek(port as u8);
+                        let on = keyboard_value.map_or_else(
+                            || self.program_executor.switches.is_on(port, bit),
+                            |value| (value >> bit) & 1 == 1,
+                        );
+                        let response =
+                            self.draw_switch(ui, lamp_size, on, keyboard_value.is_none());
+                        if response.clicked() {
+                            self.program_executor.switches.toggle(port, bit);
                         }
                     }
                     ui.end_row();
@@ -180,12 +333,12 @@ impl App {
         ui.label(RichText::new("unsigned").size(8.0));
         ui.label(RichText::new(" signed").size(8.0));
         ui.end_row();
-        for i in 0..4 {
+        for i in 0..self.program_executor.config().general_registers {
             self.draw_register_info_row(ui, &format!("R{i}"), self.program_executor.registers[i]);
         }
         ui.end_row();
         self.draw_register_info_row(ui, "PC", self.program_executor.curr_addr as u16);
-        self.draw_register_info_row(ui, "SP", self.program_executor.registers[4]);
+        self.draw_register_info_row(ui, "SP", self.program_executor.registers[SP as usize]);
         self.draw_register_info_row(ui, "PS", self.program_executor.program_state_reg);
     }
 
@@ -214,9 +367,23 @@ impl App {
         res as i32
     }
 
+    // Triggered watchpoints and breakpoints pause the program,
+    // it can be continued step by step or with "Continue".
+    fn pause_if_hit(&mut self) -> bool {
+        let is_hit = self.program_executor.watchpoint_hit.is_some()
+            || self.program_executor.breakpoint_hit.is_some();
+        if is_hit {
+            self.program_executor.is_in_debug_mode = true;
+        }
+        is_hit
+    }
+
     fn execute_instructions(&mut self) {
         for _ in 0..self.get_required_ticks_and_update() {
             self.execute_next_instruction();
+            if self.pause_if_hit() {
+                break;
+            }
         }
     }
 
@@ -227,17 +394,27 @@ impl App {
             self.program_executor.is_in_debug_mode = false;
             self.program_executor.has_finished = true;
             if !self.compilation_failed() {
-                self.program_executor.memory = self.compiler.program;
+                self.program_executor
+                    .load_program(&self.compiler.program, self.compiler.code_size);
             }
         }
         if !is_running && ui.button("Run").clicked() {
             self.program_executor.is_in_debug_mode = false;
             self.program_executor.prepare_for_a_new_run();
             if !self.compilation_failed() {
-                self.program_executor.memory = self.compiler.program;
-                self.execute_instructions();
+                self.program_executor
+                    .load_program(&self.compiler.program, self.compiler.code_size);
+                self.program_executor.check_breakpoints();
+                if !self.pause_if_hit() {
+                    self.execute_instructions();
+                }
             }
         }
+        let is_paused =
+            !self.program_executor.has_finished && self.program_executor.is_in_debug_mode;
+        if is_paused && ui.button("Continue").clicked() {
+            self.program_executor.is_in_debug_mode = false;
+        }
         if is_running && ui.button("Stop").clicked() {
             self.program_executor.is_in_debug_mode = false;
             self.program_executor.has_finished = true;
@@ -248,7 +425,9 @@ impl App {
             {
                 self.program_executor.is_in_debug_mode = true;
                 self.program_executor.prepare_for_a_new_run();
-                self.program_executor.memory = self.compiler.program;
+                self.program_executor
+                    .load_program(&self.compiler.program, self.compiler.code_size);
+                self.program_executor.check_breakpoints();
             } else {
                 self.execute_instructions();
             }
@@ -257,14 +436,66 @@ impl App {
             for i in 0..4 {
                 self.program_executor.registers[i] = 0;
             }
-            self.program_executor.registers[4] = (MAX_PROGRAM_SIZE - 1) as u16;
+            self.program_executor.registers[SP as usize] = self.program_executor.initial_sp();
             self.program_executor.program_state_reg = 0;
         }
+        ui.separator();
+        self.quick_save_slots_ui(ui);
         if is_running {
             self.execute_instructions();
         }
     }
 
+    // A restored machine is paused, so it can be inspected before it continues.
+    fn restore_snapshot(&mut self, snapshot: &Snapshot) {
+        self.program_executor.restore(snapshot);
+        self.compiler
+            .set_machine_config(self.program_executor.config());
+        self.program_executor.is_in_debug_mode = true;
+        self.error_popup_info = ErrorPopupInfo::None;
+    }
+
+    fn quick_save_slots_ui(&mut self, ui: &mut egui::Ui) {
+        for i in 0..self.quick_save_slots.len() {
+            if ui
+                .button(format!("💾{}", i + 1))
+                .on_hover_text("Quick save")
+                .clicked()
+            {
+                self.quick_save_slots[i] = Some(self.program_executor.snapshot());
+            }
+            let is_saved = self.quick_save_slots[i].is_some();
+            let button = egui::Button::new(format!("⟲{}", i + 1));
+            if ui
+                .add_enabled(is_saved, button)
+                .on_hover_text("Quick load")
+                .clicked()
+            {
+                if let Some(snapshot) = self.quick_save_slots[i].clone() {
+                    self.restore_snapshot(&snapshot);
+                }
+            }
+        }
+    }
+
+    fn snapshot_file_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("File:");
+            ui.text_edit_singleline(&mut self.snapshot_path);
+            if ui.button("Save").clicked() {
+                if let Err(err) = self.program_executor.snapshot().save(&self.snapshot_path) {
+                    self.error_popup_info = ErrorPopupInfo::FileError(err);
+                }
+            }
+            if ui.button("Load").clicked() {
+                match Snapshot::load(&self.snapshot_path) {
+                    Ok(snapshot) => self.restore_snapshot(&snapshot),
+                    Err(err) => self.error_popup_info = ErrorPopupInfo::FileError(err),
+                }
+            }
+        });
+    }
+
     fn settings_and_info_panel_ui(&mut self, ui: &mut egui::Ui, errors: &ErrorsHighlightInfo) {
         let mut is_dark_mode = ui.ctx().style().visuals.dark_mode;
         ui.horizontal(|ui| {
@@ -278,6 +509,65 @@ impl App {
             } else {
                 Visuals::light()
             });
+            ui.separator();
+            let mut case_sensitive_labels = self.compiler.case_sensitive_labels();
+            ui.checkbox(&mut case_sensitive_labels, "Case-sensitive labels");
+            self.compiler
+                .set_case_sensitive_labels(case_sensitive_labels);
+            ui.checkbox(
+                &mut self.program_executor.strict_division,
+                "Division by zero is an error",
+            );
+            egui::ComboBox::from_label("Writes to code")
+                .selected_text(Self::code_protection_name(
+                    self.program_executor.code_protection,
+                ))
+                .show_ui(ui, |ui| {
+                    for protection in [
+                        CodeProtection::Off,
+                        CodeProtection::ReadOnly,
+                        CodeProtection::Log,
+                    ] {
+                        ui.selectable_value(
+                            &mut self.program_executor.code_protection,
+                            protection,
+                            Self::code_protection_name(protection),
+                        );
+                    }
+                });
+            let memory_size = self.program_executor.config().memory_size;
+            let stack_region = self.program_executor.stack_region;
+            let mut has_stack_guard = stack_region.is_some();
+            ui.checkbox(&mut has_stack_guard, "Stack guard, bytes:");
+            let mut stack_size = stack_region.map_or(0x100, |region| region.top - region.bottom);
+            ui.add_enabled(
+                has_stack_guard,
+                egui::DragValue::new(&mut stack_size).range(2..=StackRegion::top(memory_size)),
+            );
+            self.program_executor.stack_region =
+                has_stack_guard.then(|| StackRegion::at_the_end_of_memory(stack_size, memory_size));
+            ui.checkbox(
+                &mut self.program_executor.detect_loops,
+                "Detect infinite loops",
+            );
+            let mut has_instruction_limit = self.program_executor.max_instructions.is_some();
+            ui.checkbox(&mut has_instruction_limit, "Instruction limit:");
+            let mut instruction_limit = self.program_executor.max_instructions.unwrap_or(100_000);
+            ui.add_enabled(
+                has_instruction_limit,
+                egui::DragValue::new(&mut instruction_limit).range(1..=u64::MAX),
+            );
+            self.program_executor.max_instructions =
+                has_instruction_limit.then_some(instruction_limit);
+            ui.separator();
+            self.machine_ui(ui);
+            ui.menu_button("Examples", |ui| {
+                for (name, code) in EXAMPLES {
+                    if ui.button(name).clicked() {
+                        self.code = code.to_string();
+                    }
+                }
+            });
         });
         ui.separator();
         ui.horizontal(|ui| {
@@ -304,9 +594,224 @@ impl App {
                 );
             });
         });
+        ui.label(Self::get_monospace(
+            &format!(
+                "Executed {} instructions in {} cycles",
+                self.program_executor.executed_instructions, self.program_executor.cycles
+            ),
+            10.0,
+        ));
+        if let Some(hit) = &self.program_executor.watchpoint_hit {
+            ui.label(RichText::new(format!("⏸ Watchpoint {hit}")).color(Color32::YELLOW));
+        }
+        if let Some(i) = self.program_executor.breakpoint_hit {
+            let breakpoint = &self.program_executor.breakpoints[i];
+            ui.label(RichText::new(format!("⏸ Breakpoint {breakpoint}")).color(Color32::YELLOW));
+        }
+        ui.collapsing("Watchpoints", |ui| self.watchpoints_ui(ui));
+        ui.collapsing("Breakpoints", |ui| self.breakpoints_ui(ui));
+        ui.collapsing("Snapshot", |ui| self.snapshot_file_ui(ui));
+        ui.collapsing("Trace", |ui| self.trace_ui(ui));
+        self.code_writes_ui(ui);
         self.error_messages_list_ui(ui, errors);
     }
 
+    fn breakpoints_ui(&mut self, ui: &mut egui::Ui) {
+        let mut removed = None;
+        for (i, breakpoint) in self.program_executor.breakpoints.iter().enumerate() {
+            ui.horizontal(|ui| {
+                ui.label(Self::get_monospace(&breakpoint.to_string(), 10.0));
+                if ui.small_button("🗑").clicked() {
+                    removed = Some(i);
+                }
+            });
+        }
+        if let Some(i) = removed {
+            self.program_executor.breakpoints.remove(i);
+            self.program_executor.breakpoint_hit = None;
+        }
+        let address = wrapping_parse(self.new_breakpoint_address.trim())
+            .map(|addr| addr as usize)
+            .filter(|&addr| addr < self.program_executor.memory.len());
+        let breakpoint = Breakpoint::new(
+            address.unwrap_or(0),
+            &self.new_breakpoint_condition,
+            &self.program_executor.config(),
+        );
+        let mut add_clicked = false;
+        ui.horizontal(|ui| {
+            ui.label("at");
+            ui.add(
+                egui::TextEdit::singleline(&mut self.new_breakpoint_address)
+                    .hint_text("0x10")
+                    .desired_width(50.0),
+            );
+            ui.label("if");
+            ui.add(
+                egui::TextEdit::singleline(&mut self.new_breakpoint_condition)
+                    .hint_text("r0 == 10 && hits > 5")
+                    .desired_width(160.0),
+            );
+            let can_add = address.is_some() && breakpoint.is_ok();
+            add_clicked = ui.add_enabled(can_add, egui::Button::new("Add")).clicked();
+        });
+        if !self.new_breakpoint_address.is_empty() && address.is_none() {
+            ui.label(RichText::new("Invalid address").color(Color32::RED));
+        }
+        match breakpoint {
+            Err(err) => {
+                ui.label(RichText::new(format!("Condition: {err}")).color(Color32::RED));
+            }
+            Ok(breakpoint) if add_clicked => {
+                self.program_executor.breakpoints.push(breakpoint);
+                self.new_breakpoint_address.clear();
+                self.new_breakpoint_condition.clear();
+            }
+            Ok(_) => {}
+        }
+    }
+
+    // Changing the machine turns it off, the program has to be run again.
+    fn machine_ui(&mut self, ui: &mut egui::Ui) {
+        let mut config = self.program_executor.config();
+        egui::ComboBox::from_label("Machine")
+            .selected_text(config.to_string())
+            .show_ui(ui, |ui| {
+                for (_, preset) in PRESETS {
+                    ui.selectable_value(&mut config, preset, preset.to_string());
+                }
+            });
+        if config != self.program_executor.config() {
+            self.set_machine_config(config);
+        }
+    }
+
+    fn set_machine_config(&mut self, config: MachineConfig) {
+        self.program_executor.set_machine_config(config);
+        self.compiler.set_machine_config(config);
+        self.error_popup_info = ErrorPopupInfo::None;
+    }
+
+    fn code_protection_name(protection: CodeProtection) -> &'static str {
+        match protection {
+            CodeProtection::Off => "allowed",
+            CodeProtection::ReadOnly => "are errors",
+            CodeProtection::Log => "are logged",
+        }
+    }
+
+    fn code_writes_ui(&mut self, ui: &mut egui::Ui) {
+        let code_writes = &self.program_executor.code_writes;
+        if code_writes.is_empty() {
+            return;
+        }
+        ui.collapsing(format!("Writes to code ({})", code_writes.len()), |ui| {
+            egui::ScrollArea::vertical()
+                .max_height(80.0)
+                .show(ui, |ui| {
+                    for write in code_writes {
+                        ui.label(Self::get_monospace(
+                            &format!("0x{:x}: wrote to 0x{:x}", write.err_address, write.address),
+                            10.0,
+                        ));
+                    }
+                });
+        });
+    }
+
+    fn watchpoints_ui(&mut self, ui: &mut egui::Ui) {
+        let mut removed = None;
+        for (i, watchpoint) in self.program_executor.watchpoints.iter().enumerate() {
+            ui.horizontal(|ui| {
+                ui.label(Self::get_monospace(&watchpoint.to_string(), 10.0));
+                if ui.small_button("🗑").clicked() {
+                    removed = Some(i);
+                }
+            });
+        }
+        if let Some(i) = removed {
+            self.program_executor.watchpoints.remove(i);
+        }
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_salt("New watchpoint kind")
+                .selected_text(self.new_watchpoint_kind.to_string())
+                .show_ui(ui, |ui| {
+                    for kind in [WatchKind::Read, WatchKind::Write, WatchKind::Change] {
+                        ui.selectable_value(&mut self.new_watchpoint_kind, kind, kind.to_string());
+                    }
+                });
+            ui.label("of");
+            ui.add(
+                egui::TextEdit::singleline(&mut self.new_watchpoint_target)
+                    .hint_text("r0, sp, p3, 0x800..0x810")
+                    .desired_width(140.0),
+            );
+            let target =
+                WatchTarget::parse(&self.new_watchpoint_target, &self.program_executor.config());
+            let response = ui.add_enabled(target.is_some(), egui::Button::new("Add"));
+            if let Some(target) = target {
+                if response.clicked() {
+                    self.program_executor.watchpoints.push(Watchpoint {
+                        target,
+                        kind: self.new_watchpoint_kind,
+                    });
+                    self.new_watchpoint_target.clear();
+                }
+            } else if !self.new_watchpoint_target.is_empty() {
+                response.on_disabled_hover_text("Invalid register, port or address");
+            }
+        });
+    }
+
+    // Ranges take effect right away, already recorded instructions stay in the trace.
+    fn trace_ui(&mut self, ui: &mut egui::Ui) {
+        let ranges: Option<Vec<Range<usize>>> = self
+            .trace_ranges
+            .split(',')
+            .filter(|range| !range.trim().is_empty())
+            .map(parse_address_range)
+            .collect();
+        ui.horizontal(|ui| {
+            let mut is_tracing = self.program_executor.tracer.is_some();
+            ui.checkbox(&mut is_tracing, "Record, addresses:");
+            ui.add(
+                egui::TextEdit::singleline(&mut self.trace_ranges)
+                    .hint_text("all or 0x10..0x40, 0x80..0x90")
+                    .desired_width(180.0),
+            );
+            if ranges.is_none() {
+                ui.label(RichText::new("Invalid address range").color(Color32::RED));
+            }
+            let tracer = &mut self.program_executor.tracer;
+            if !is_tracing {
+                *tracer = None;
+            } else if let Some(tracer) = tracer {
+                if let Some(ranges) = ranges {
+                    tracer.address_ranges = ranges;
+                }
+            } else {
+                *tracer = Some(Tracer::with_address_ranges(ranges.unwrap_or_default()));
+            }
+        });
+        let Some(tracer) = &mut self.program_executor.tracer else {
+            return;
+        };
+        ui.horizontal(|ui| {
+            ui.label(format!("{} instructions", tracer.records.len()));
+            if ui.button("Clear").clicked() {
+                tracer.clear();
+            }
+            ui.separator();
+            ui.label("File (.jsonl or .csv):");
+            ui.text_edit_singleline(&mut self.trace_path);
+            if ui.button("Export").clicked() {
+                if let Err(err) = tracer.export(&self.trace_path) {
+                    self.error_popup_info = ErrorPopupInfo::FileError(err);
+                }
+            }
+        });
+    }
+
     fn error_messages_list_ui(&mut self, ui: &mut egui::Ui, errors: &ErrorsHighlightInfo) {
         let mut error_messages: Vec<String> =
             errors.iter().map(|(_, err)| format!("{err}")).collect();
@@ -318,6 +823,7 @@ impl App {
             egui::TextEdit::multiline(&mut error_messages.join("\n").as_str())
                 .desired_rows(0)
                 .ui(ui);
+            ui.horizontal_wrapped(|ui| self.quick_fix_buttons_ui(ui, errors));
         });
     }
 
@@ -326,6 +832,7 @@ impl App {
             ErrorPopupInfo::None => return,
             ErrorPopupInfo::CompilationError(err) => ("Compilation error", err.to_string()),
             ErrorPopupInfo::RuntimeError(err) => ("Runtime error", err.to_string()),
+            ErrorPopupInfo::FileError(err) => ("File error", err.clone()),
         };
         let mut is_opened = !matches!(&self.error_popup_info, ErrorPopupInfo::None);
         egui::Window::new(RichText::new(title).color(Color32::RED))
@@ -344,9 +851,25 @@ impl App {
     fn get_hex_viewer_rows(&self, rows_range: Range<usize>, theme: &CodeTheme) -> LayoutJob {
         let mut layout_job = LayoutJob::default();
         layout_job.text.reserve(rows_range.len() * 8);
-        let range = (rows_range.start * 8)..(rows_range.end * 8).min(MAX_PROGRAM_SIZE);
+        let memory_size = self.program_executor.memory.len();
+        let range = (rows_range.start * 8)..(rows_range.end * 8).min(memory_size);
         let text_format = theme.formats[TokenType::Punctuation].clone();
         let highlighted_format = theme.formats[TokenType::Label].clone();
+        let stack_format = theme.formats[TokenType::Number].clone();
+        let stack_region = self.program_executor.stack_region;
+        let modified_code_format = TextFormat {
+            underline: Stroke::new(1.0, Color32::RED),
+            ..theme.formats[TokenType::Keyword].clone()
+        };
+        let current_instruction = if self.program_executor.has_finished {
+            0..0
+        } else {
+            let size = self
+                .program_executor
+                .get_current_instruction_size()
+                .unwrap_or(1);
+            self.program_executor.curr_addr..(self.program_executor.curr_addr + size)
+        };
         for i in range.clone() {
             if (i & 0b111) == 0 {
                 if i != range.start {
@@ -363,8 +886,12 @@ impl App {
             layout_job.append(
                 &format!("{:#04x}", self.program_executor.memory[i]).to_ascii_uppercase()[2..],
                 0.0,
-                if i == self.program_executor.curr_addr && !self.program_executor.has_finished {
+                if current_instruction.contains(&i) {
                     highlighted_format.clone()
+                } else if self.program_executor.modified_code.contains(&i) {
+                    modified_code_format.clone()
+                } else if stack_region.is_some_and(|region| region.contains(i)) {
+                    stack_format.clone()
                 } else {
                     text_format.clone()
                 },
@@ -374,10 +901,11 @@ impl App {
     }
 
     fn hex_viewer_ui(&self, ui: &mut egui::Ui, theme: &CodeTheme) {
+        let rows = self.program_executor.memory.len() / 8;
         ui.push_id("Binary code viewer", |ui| {
             egui::ScrollArea::vertical()
                 .min_scrolled_height(ui.available_height())
-                .show_rows(ui, 8.0, MAX_PROGRAM_SIZE / 8, |ui, rows_range| {
+                .show_rows(ui, 8.0, rows, |ui, rows_range| {
                     let mut layout_job =
                         self.get_hex_viewer_rows(rows_range.start..(rows_range.end + 5), theme);
                     ui.add(
@@ -405,16 +933,20 @@ impl eframe::App for App {
         self.show_error_popup(ctx);
         let theme = CodeTheme::from_memory(ctx);
         egui_extras::install_image_loaders(ctx);
-        self.compiler.compile_code(&self.code);
+        self.compiler.compile_if_changed(&self.code);
+        self.handle_keyboard_input(ctx);
         egui::TopBottomPanel::top("Light bulbs and registers")
             .resizable(true)
             .min_height(self.last_info_panel_height)
             .default_height(128.0)
             .show(ctx, |ui| {
                 let available = ui.available_size();
-                let panel_size = available.y.min(available.x * 0.5);
+                // Lamps and switches share the left part of the panel.
+                let panel_size = available.y.min(available.x * 0.3);
                 let ppp = ui.ctx().pixels_per_point();
-                let lamp_size = (panel_size * ppp / 16.0).round() / ppp;
+                let config = self.program_executor.config();
+                let lamps = config.display_width.max(config.display_height) as f32;
+                let lamp_size = (panel_size * ppp / lamps).round() / ppp;
                 ui.horizontal_top(|ui| {
                     self.light_bulbs_panel_ui(ui, lamp_size);
                     ui.add(egui::Separator::default().vertical().spacing(10.0));
@@ -433,7 +965,8 @@ impl eframe::App for App {
         egui::CentralPanel::default().show(ctx, |ui| {
             theme.clone().store_in_memory(ui.ctx());
             ui.horizontal_top(|ui| {
-                self.code_editor_ui(ui, &theme, &self.compiler.errors.clone());
+                let keywords = self.compiler.instruction_names();
+                self.code_editor_ui(ui, &theme, &self.compiler.errors.clone(), &keywords);
                 self.hex_viewer_ui(ui, &theme);
             });
         });
@@ -0,0 +1,336 @@
+// Snapshots of the whole machine: memory, registers, PS, PC, lamps and input devices.
+//
+// File format, all numbers are big-endian like the words in memory:
+//   magic `RSNP`, version: u16,
+//   instruction set version: u16,
+//   machine: memory size: u32, display width: u16, display height: u16, general registers: u8,
+//   memory size: u32, memory, code size: u32,
+//   registers: 5 x u16, PS: u16, PC: u32, has finished: u8,
+//   lamps: u16 per port, switches: u16 per port,
+//   held keys: u16, number of queued keys: u8, queued keys: u16 each,
+//   executed instructions: u64, cycles: u64.
+
+use crate::devices::{Keyboard, SwitchPanel};
+use crate::instruction_set::{FIRST_COMPATIBLE_ISA_VERSION, ISA_VERSION};
+use crate::machine::MachineConfig;
+use std::fmt::{Display, Formatter};
+
+const MAGIC: &[u8; 4] = b"RSNP";
+pub const SNAPSHOT_VERSION: u16 = 1;
+
+#[derive(Clone)]
+pub struct Snapshot {
+    pub config: MachineConfig,
+    pub memory: Vec<u8>,
+    pub code_size: usize,
+    pub registers: [u16; 5],
+    pub program_state_reg: u16,
+    pub curr_addr: usize,
+    pub has_finished: bool,
+    pub display: Vec<u16>,
+    pub switches: SwitchPanel,
+    pub keyboard: Keyboard,
+    pub executed_instructions: u64,
+    pub cycles: u64,
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    NotASnapshot,
+    UnsupportedVersion { version: u16 },
+    IncompatibleIsa { version: u16 },
+    UnexpectedEnd,
+    TrailingBytes,
+    InvalidMachine { message: String },
+    WrongMemorySize { size: usize, expected: usize },
+    InvalidField { name: &'static str },
+}
+
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotError::NotASnapshot => write!(f, "Not a snapshot file"),
+            SnapshotError::UnsupportedVersion { version } => write!(
+                f,
+                "Unsupported snapshot version {version}, expected version {SNAPSHOT_VERSION}"
+            ),
+            SnapshotError::IncompatibleIsa { version } => write!(
+                f,
+                "The snapshot was made for instruction set version {version}, \
+                supported versions are {FIRST_COMPATIBLE_ISA_VERSION} to {ISA_VERSION}"
+            ),
+            SnapshotError::UnexpectedEnd => write!(f, "The snapshot is cut off"),
+            SnapshotError::TrailingBytes => write!(f, "Unexpected bytes after the snapshot"),
+            SnapshotError::InvalidMachine { message } => {
+                write!(f, "Invalid machine in the snapshot: {message}")
+            }
+            SnapshotError::WrongMemorySize { size, expected } => write!(
+                f,
+                "The snapshot has {size} bytes of memory, expected {expected}"
+            ),
+            SnapshotError::InvalidField { name } => write!(f, "Invalid {name} in the snapshot"),
+        }
+    }
+}
+
+fn push_words(bytes: &mut Vec<u8>, words: &[u16]) {
+    for word in words {
+        bytes.extend(word.to_be_bytes());
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], SnapshotError> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], SnapshotError> {
+        if self.bytes.len() < n {
+            return Err(SnapshotError::UnexpectedEnd);
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, SnapshotError> {
+        Ok(u16::from_be_bytes(self.take()?))
+    }
+
+    fn u32(&mut self) -> Result<usize, SnapshotError> {
+        Ok(u32::from_be_bytes(self.take()?) as usize)
+    }
+
+    fn u64(&mut self) -> Result<u64, SnapshotError> {
+        Ok(u64::from_be_bytes(self.take()?))
+    }
+
+    fn words<const N: usize>(&mut self) -> Result<[u16; N], SnapshotError> {
+        let mut words = [0; N];
+        for word in &mut words {
+            *word = self.u16()?;
+        }
+        Ok(words)
+    }
+
+    fn word_vec(&mut self, n: usize) -> Result<Vec<u16>, SnapshotError> {
+        (0..n).map(|_| self.u16()).collect()
+    }
+}
+
+impl Snapshot {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(SNAPSHOT_VERSION.to_be_bytes());
+        bytes.extend(ISA_VERSION.to_be_bytes());
+        bytes.extend((self.config.memory_size as u32).to_be_bytes());
+        bytes.extend((self.config.display_width as u16).to_be_bytes());
+        bytes.extend((self.config.display_height as u16).to_be_bytes());
+        bytes.push(self.config.general_registers as u8);
+        bytes.extend((self.memory.len() as u32).to_be_bytes());
+        bytes.extend(&self.memory);
+        bytes.extend((self.code_size as u32).to_be_bytes());
+        push_words(&mut bytes, &self.registers);
+        bytes.extend(self.program_state_reg.to_be_bytes());
+        bytes.extend((self.curr_addr as u32).to_be_bytes());
+        bytes.push(self.has_finished as u8);
+        push_words(&mut bytes, &self.display);
+        push_words(&mut bytes, self.switches.rows());
+        bytes.extend(self.keyboard.held_keys().to_be_bytes());
+        let queue = self.keyboard.queued_keys();
+        bytes.push(queue.len() as u8);
+        push_words(&mut bytes, &queue);
+        bytes.extend(self.executed_instructions.to_be_bytes());
+        bytes.extend(self.cycles.to_be_bytes());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        let mut reader = Reader { bytes };
+        if reader.take::<4>().ok().as_ref() != Some(MAGIC) {
+            return Err(SnapshotError::NotASnapshot);
+        }
+        let version = reader.u16()?;
+        if version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion { version });
+        }
+        let isa_version = reader.u16()?;
+        if !(FIRST_COMPATIBLE_ISA_VERSION..=ISA_VERSION).contains(&isa_version) {
+            return Err(SnapshotError::IncompatibleIsa {
+                version: isa_version,
+            });
+        }
+        let config = MachineConfig {
+            memory_size: reader.u32()?,
+            display_width: reader.u16()? as usize,
+            display_height: reader.u16()? as usize,
+            general_registers: reader.u8()? as usize,
+        };
+        config
+            .validate()
+            .map_err(|message| SnapshotError::InvalidMachine { message })?;
+        let memory_size = reader.u32()?;
+        if memory_size != config.memory_size {
+            return Err(SnapshotError::WrongMemorySize {
+                size: memory_size,
+                expected: config.memory_size,
+            });
+        }
+        let memory = reader.bytes(memory_size)?.to_vec();
+        let code_size = reader.u32()?;
+        if code_size > memory_size {
+            return Err(SnapshotError::InvalidField { name: "code size" });
+        }
+        let registers = reader.words()?;
+        let program_state_reg = reader.u16()?;
+        let curr_addr = reader.u32()?;
+        let has_finished = match reader.u8()? {
+            0 => false,
+            1 => true,
+            _ => return Err(SnapshotError::InvalidField { name: "state" }),
+        };
+        let display = reader.word_vec(config.ports())?;
+        let switches = SwitchPanel::from_rows(reader.word_vec(config.ports())?);
+        let held_keys = reader.u16()?;
+        let queue_len = reader.u8()? as usize;
+        let queue = reader.word_vec(queue_len)?;
+        let snapshot = Self {
+            config,
+            memory,
+            code_size,
+            registers,
+            program_state_reg,
+            curr_addr,
+            has_finished,
+            display,
+            switches,
+            keyboard: Keyboard::from_parts(held_keys, &queue),
+            executed_instructions: reader.u64()?,
+            cycles: reader.u64()?,
+        };
+        if !reader.bytes.is_empty() {
+            return Err(SnapshotError::TrailingBytes);
+        }
+        Ok(snapshot)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        std::fs::write(path, self.encode()).map_err(|err| format!("Can't write `{path}`: {err}"))
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let bytes = std::fs::read(path).map_err(|err| format!("Can't read `{path}`: {err}"))?;
+        Self::decode(&bytes).map_err(|err| format!("`{path}`: {err}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::{KEY_ENTER, KEY_UP};
+    use crate::executor::ProgramExecutor;
+
+    fn encoded_machine() -> Vec<u8> {
+        let mut executor = ProgramExecutor::default();
+        executor.set_machine_config(MachineConfig::preset("minimal").unwrap());
+        executor.load_program(&[0x12, 0x34, 0x56], 3);
+        executor.memory[0x3ff] = 0xab;
+        executor.registers = [1, 2, 0xfffe, 0x8000, 0x3f0];
+        executor.program_state_reg = 0x9;
+        executor.curr_addr = 0x20;
+        executor.has_finished = false;
+        executor.display[3] = 0xf00f;
+        executor.switches.toggle(0, 0);
+        executor.switches.toggle(15, 15);
+        executor.keyboard.press(b'A' as u16);
+        executor.keyboard.press(KEY_UP);
+        executor.keyboard.press(KEY_ENTER);
+        executor.keyboard.release(KEY_ENTER);
+        executor.executed_instructions = 1234;
+        executor.cycles = 5678;
+        executor.snapshot().encode()
+    }
+
+    #[test]
+    fn restored_machines_are_the_same() {
+        let bytes = encoded_machine();
+        let snapshot = Snapshot::decode(&bytes).unwrap();
+        let mut executor = ProgramExecutor::default();
+        executor.restore(&snapshot);
+
+        assert_eq!(executor.config(), MachineConfig::preset("minimal").unwrap());
+        assert_eq!(executor.memory[..4], [0x12, 0x34, 0x56, 0]);
+        assert_eq!(executor.memory[0x3ff], 0xab);
+        assert_eq!(executor.code_size, 3);
+        assert_eq!(executor.registers, [1, 2, 0xfffe, 0x8000, 0x3f0]);
+        assert_eq!(executor.program_state_reg, 0x9);
+        assert_eq!(executor.curr_addr, 0x20);
+        assert!(!executor.has_finished);
+        assert_eq!(executor.display[3], 0xf00f);
+        assert!(executor.switches.is_on(0, 0) && executor.switches.is_on(15, 15));
+        assert!(!executor.switches.is_on(0, 1));
+        // Only the up arrow is still held.
+        assert_eq!(executor.keyboard.held_keys(), 1 << 1);
+        assert_eq!(
+            executor.keyboard.queued_keys(),
+            [b'A' as u16, KEY_UP, KEY_ENTER]
+        );
+        assert_eq!(executor.executed_instructions, 1234);
+        assert_eq!(executor.cycles, 5678);
+        assert_eq!(executor.snapshot().encode(), bytes);
+    }
+
+    #[test]
+    fn truncated_snapshots_are_rejected() {
+        let bytes = encoded_machine();
+        for len in [0, 3, 5, 20, bytes.len() / 2, bytes.len() - 1] {
+            let result = Snapshot::decode(&bytes[..len]);
+            if len < MAGIC.len() {
+                assert!(matches!(result, Err(SnapshotError::NotASnapshot)));
+            } else {
+                assert!(
+                    matches!(result, Err(SnapshotError::UnexpectedEnd)),
+                    "length {len}"
+                );
+            }
+        }
+        let mut longer = bytes;
+        longer.push(0);
+        assert!(matches!(
+            Snapshot::decode(&longer),
+            Err(SnapshotError::TrailingBytes)
+        ));
+    }
+
+    #[test]
+    fn other_files_and_versions_are_rejected() {
+        let mut bytes = encoded_machine();
+        bytes[0] = b'X';
+        assert!(matches!(
+            Snapshot::decode(&bytes),
+            Err(SnapshotError::NotASnapshot)
+        ));
+
+        let mut bytes = encoded_machine();
+        bytes[4..6].copy_from_slice(&2u16.to_be_bytes());
+        assert!(matches!(
+            Snapshot::decode(&bytes),
+            Err(SnapshotError::UnsupportedVersion { version: 2 })
+        ));
+
+        let mut bytes = encoded_machine();
+        bytes[6..8].copy_from_slice(&(ISA_VERSION + 1).to_be_bytes());
+        assert!(matches!(
+            Snapshot::decode(&bytes),
+            Err(SnapshotError::IncompatibleIsa { .. })
+        ));
+    }
+}
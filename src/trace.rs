@@ -0,0 +1,277 @@
+// Execution trace: one record per executed instruction with everything it read and changed.
+// Exported as JSON Lines or CSV, e.g. for grading.
+
+use crate::highlighting::wrapping_parse;
+use crate::instruction_set::{InstructionOperand, InstructionOperands};
+use std::fmt::Write;
+use std::ops::Range;
+
+// Long runs are traced only up to this many instructions.
+pub const MAX_TRACE_RECORDS: usize = 1_000_000;
+
+const REGISTER_NAMES: [&str; 5] = ["r0", "r1", "r2", "r3", "sp"];
+
+pub struct TraceRecord {
+    // Number of instructions executed before this one.
+    pub step: u64,
+    pub pc: usize,
+    pub instruction: String,
+    // Values read from the operands, in the order the instruction read them.
+    pub operand_values: Vec<u16>,
+    // Register, old value, new value.
+    pub register_changes: Vec<(usize, u16, u16)>,
+    pub ps_change: Option<(u16, u16)>,
+    // Written bytes, a word is written as two bytes.
+    pub memory_writes: Vec<(u16, u8)>,
+    pub port_writes: Vec<(u8, u16)>,
+}
+
+#[derive(Default)]
+pub struct Tracer {
+    // Only instructions at these addresses are traced, all of them if it's empty.
+    pub address_ranges: Vec<Range<usize>>,
+    pub records: Vec<TraceRecord>,
+    // The record of the instruction that is being executed.
+    current: Option<(TraceRecord, [u16; 5], u16)>,
+}
+
+// Parses an address range like `0x10..0x40`, the end is excluded.
+pub fn parse_address_range(text: &str) -> Option<Range<usize>> {
+    let text = text.trim().to_ascii_lowercase();
+    let (start, end) = text.split_once("..")?;
+    let range = wrapping_parse(start.trim())? as usize..wrapping_parse(end.trim())? as usize;
+    (!range.is_empty()).then_some(range)
+}
+
+// Assembler syntax of an operand, e.g. `(r1)+` or `0x4(sp)`.
+fn operand_text(operand: InstructionOperand) -> String {
+    let reg = |reg: u8| REGISTER_NAMES[reg as usize];
+    match operand {
+        InstructionOperand::Reg(r) => reg(r).to_string(),
+        InstructionOperand::Addr(r) => format!("({})", reg(r)),
+        InstructionOperand::AddrInc(r) => format!("({})+", reg(r)),
+        InstructionOperand::AddrDec(r) => format!("-({})", reg(r)),
+        InstructionOperand::Indexed(r, offset) => format!("0x{offset:x}({})", reg(r)),
+        InstructionOperand::Absolute(addr) => format!("(0x{addr:x})"),
+        InstructionOperand::Port(p) => format!("p{p}"),
+        InstructionOperand::Number(num) => format!("0x{num:x}"),
+    }
+}
+
+pub fn disassemble(name: &str, operands: InstructionOperands) -> String {
+    match operands {
+        InstructionOperands::Zero => name.to_string(),
+        InstructionOperands::One(op) => format!("{name} {}", operand_text(op)),
+        InstructionOperands::Two(op1, op2) => {
+            format!("{name} {}, {}", operand_text(op1), operand_text(op2))
+        }
+    }
+}
+
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+impl Tracer {
+    pub fn with_address_ranges(address_ranges: Vec<Range<usize>>) -> Self {
+        Self {
+            address_ranges,
+            ..Self::default()
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.records.clear();
+        self.current = None;
+    }
+
+    fn is_traced(&self, pc: usize) -> bool {
+        self.address_ranges.is_empty()
+            || self.address_ranges.iter().any(|range| range.contains(&pc))
+    }
+
+    pub fn begin(
+        &mut self,
+        step: u64,
+        pc: usize,
+        instruction: String,
+        registers: [u16; 5],
+        ps: u16,
+    ) {
+        self.current = None;
+        if self.records.len() >= MAX_TRACE_RECORDS || !self.is_traced(pc) {
+            return;
+        }
+        let record = TraceRecord {
+            step,
+            pc,
+            instruction,
+            operand_values: vec![],
+            register_changes: vec![],
+            ps_change: None,
+            memory_writes: vec![],
+            port_writes: vec![],
+        };
+        self.current = Some((record, registers, ps));
+    }
+
+    // Instructions that failed aren't recorded.
+    pub fn finish(&mut self, registers: [u16; 5], ps: u16) {
+        let Some((mut record, old_registers, old_ps)) = self.current.take() else {
+            return;
+        };
+        for (i, (&old, &new)) in old_registers.iter().zip(&registers).enumerate() {
+            if old != new {
+                record.register_changes.push((i, old, new));
+            }
+        }
+        record.ps_change = (old_ps != ps).then_some((old_ps, ps));
+        self.records.push(record);
+    }
+
+    fn record(&mut self, f: impl FnOnce(&mut TraceRecord)) {
+        if let Some((record, _, _)) = &mut self.current {
+            f(record);
+        }
+    }
+
+    pub fn operand_read(&mut self, value: u16) {
+        self.record(|record| record.operand_values.push(value));
+    }
+
+    pub fn memory_write(&mut self, addr: u16, value: u8) {
+        self.record(|record| record.memory_writes.push((addr, value)));
+    }
+
+    pub fn port_write(&mut self, port: u8, value: u16) {
+        self.record(|record| record.port_writes.push((port, value)));
+    }
+
+    // One JSON object per line:
+    // {"step":0,"pc":0,"instruction":"mov r0, 0x5","operands":[5],"registers":{"r0":[0,5]},
+    //  "ps":null,"memory":[],"ports":[]}
+    // Register changes and "ps" are [old, new], memory writes are [address, byte],
+    // port writes are [port, value].
+    pub fn to_json_lines(&self) -> String {
+        let mut out = String::new();
+        for record in &self.records {
+            let list = |items: Vec<String>| items.join(",");
+            let registers = record
+                .register_changes
+                .iter()
+                .map(|(reg, old, new)| format!("\"{}\":[{old},{new}]", REGISTER_NAMES[*reg]))
+                .collect();
+            let ps = record
+                .ps_change
+                .map_or("null".to_string(), |(old, new)| format!("[{old},{new}]"));
+            let pairs = |pairs: Vec<(u16, u16)>| {
+                list(pairs.iter().map(|(a, b)| format!("[{a},{b}]")).collect())
+            };
+            let memory = record.memory_writes.iter();
+            let ports = record.port_writes.iter();
+            writeln!(
+                out,
+                "{{\"step\":{},\"pc\":{},\"instruction\":\"{}\",\"operands\":[{}],\
+                \"registers\":{{{}}},\"ps\":{ps},\"memory\":[{}],\"ports\":[{}]}}",
+                record.step,
+                record.pc,
+                record.instruction,
+                list(record.operand_values.iter().map(u16::to_string).collect()),
+                list(registers),
+                pairs(memory.map(|&(addr, byte)| (addr, byte as u16)).collect()),
+                pairs(ports.map(|&(port, value)| (port as u16, value)).collect()),
+            )
+            .unwrap();
+        }
+        out
+    }
+
+    // Lists inside of a field are separated by spaces, changes are written as `old->new`.
+    pub fn to_csv(&self) -> String {
+        let mut out = "step,pc,instruction,operands,registers,ps,memory,ports\n".to_string();
+        for record in &self.records {
+            let operands: Vec<String> = record
+                .operand_values
+                .iter()
+                .map(|value| format!("0x{value:x}"))
+                .collect();
+            let registers: Vec<String> = record
+                .register_changes
+                .iter()
+                .map(|(reg, old, new)| format!("{}=0x{old:x}->0x{new:x}", REGISTER_NAMES[*reg]))
+                .collect();
+            let ps = record
+                .ps_change
+                .map_or(String::new(), |(old, new)| format!("0x{old:x}->0x{new:x}"));
+            let memory: Vec<String> = record
+                .memory_writes
+                .iter()
+                .map(|(addr, byte)| format!("0x{addr:x}=0x{byte:x}"))
+                .collect();
+            let ports: Vec<String> = record
+                .port_writes
+                .iter()
+                .map(|(port, value)| format!("p{port}=0x{value:x}"))
+                .collect();
+            writeln!(
+                out,
+                "{},0x{:x},{},{},{},{ps},{},{}",
+                record.step,
+                record.pc,
+                csv_field(&record.instruction),
+                operands.join(" "),
+                registers.join(" "),
+                memory.join(" "),
+                ports.join(" "),
+            )
+            .unwrap();
+        }
+        out
+    }
+
+    // CSV for `.csv` files, JSON Lines otherwise.
+    pub fn export(&self, path: &str) -> Result<(), String> {
+        let text = if path.to_ascii_lowercase().ends_with(".csv") {
+            self.to_csv()
+        } else {
+            self.to_json_lines()
+        };
+        std::fs::write(path, text).map_err(|err| format!("Can't write `{path}`: {err}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::executor::ProgramExecutor;
+
+    fn operand_values(code: &str) -> Vec<Vec<u16>> {
+        let mut compiler = Compiler::build();
+        compiler.compile_code(code);
+        assert!(compiler.errors.is_empty());
+
+        let mut executor = ProgramExecutor::default();
+        executor.tracer = Some(Tracer::default());
+        executor.prepare_for_a_new_run();
+        executor.load_program(&compiler.program, compiler.code_size);
+        while !executor.has_finished {
+            executor.execute_next_instruction().unwrap();
+        }
+        let records = &executor.tracer.unwrap().records;
+        records
+            .iter()
+            .map(|record| record.operand_values.clone())
+            .collect()
+    }
+
+    #[test]
+    fn overwritten_destinations_arent_recorded() {
+        let values = operand_values("mov r0, 5\nadd r0, 2\nmovb r1, r0\nstop\n");
+        assert_eq!(values[..3], [vec![5], vec![2, 5], vec![7]]);
+    }
+}
@@ -0,0 +1,162 @@
+use crate::highlighting::wrapping_parse;
+use crate::machine::{MachineConfig, SP};
+use std::fmt::{Display, Formatter};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum WatchTarget {
+    // First and last byte of the watched memory.
+    Memory(u16, u16),
+    Register(u8),
+    Port(u8),
+}
+
+impl WatchTarget {
+    // Parses a general register of the machine, `sp`, `p0`..`p255`, an address
+    // or an address range like `0x800..0x810`.
+    pub fn parse(text: &str, config: &MachineConfig) -> Option<Self> {
+        let text = text.trim().to_ascii_lowercase();
+        if text == "sp" {
+            return Some(WatchTarget::Register(SP));
+        }
+        if let Some(reg) = text.strip_prefix('r') {
+            return reg
+                .parse()
+                .ok()
+                .filter(|&reg| reg != SP && config.has_register(reg))
+                .map(WatchTarget::Register);
+        }
+        if let Some(port) = text.strip_prefix('p') {
+            return port.parse().ok().map(WatchTarget::Port);
+        }
+        if let Some((start, end)) = text.split_once("..") {
+            let (start, end) = (wrapping_parse(start)?, wrapping_parse(end)?);
+            return (start < end).then(|| WatchTarget::Memory(start, end - 1));
+        }
+        wrapping_parse(&text).map(|addr| WatchTarget::Memory(addr, addr))
+    }
+
+    pub fn contains_memory(&self, first: u16, last: u16) -> bool {
+        matches!(*self, WatchTarget::Memory(start, end) if first <= end && start <= last)
+    }
+}
+
+impl Display for WatchTarget {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            WatchTarget::Memory(start, end) if start == end => write!(f, "0x{start:x}"),
+            WatchTarget::Memory(start, end) => write!(f, "0x{start:x}..0x{:x}", end as u32 + 1),
+            WatchTarget::Register(4) => write!(f, "SP"),
+            WatchTarget::Register(reg) => write!(f, "R{reg}"),
+            WatchTarget::Port(port) => write!(f, "P{port}"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum WatchKind {
+    Read,
+    Write,
+    // A write of a different value.
+    Change,
+}
+
+impl Display for WatchKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            WatchKind::Read => "read",
+            WatchKind::Write => "write",
+            WatchKind::Change => "change",
+        };
+        write!(f, "{str}")
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Watchpoint {
+    pub target: WatchTarget,
+    pub kind: WatchKind,
+}
+
+impl Display for Watchpoint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} of {}", self.kind, self.target)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct WatchpointHit {
+    pub watchpoint: Watchpoint,
+    // Address of the instruction that triggered the watchpoint.
+    pub address: usize,
+}
+
+impl Display for WatchpointHit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "0x{:x}: {}", self.address, self.watchpoint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::executor::ProgramExecutor;
+
+    // Index of the first executed instruction that reads `target`.
+    fn first_read(code: &str, target: &str) -> Option<usize> {
+        let mut compiler = Compiler::build();
+        compiler.compile_code(code);
+        assert!(compiler.errors.is_empty());
+
+        let mut executor = ProgramExecutor::default();
+        executor.watchpoints.push(Watchpoint {
+            target: WatchTarget::parse(target, &executor.config()).unwrap(),
+            kind: WatchKind::Read,
+        });
+        executor.prepare_for_a_new_run();
+        executor.load_program(&compiler.program, compiler.code_size);
+        let mut step = 0;
+        while !executor.has_finished {
+            executor.execute_next_instruction().unwrap();
+            if executor.watchpoint_hit.is_some() {
+                return Some(step);
+            }
+            step += 1;
+        }
+        None
+    }
+
+    #[test]
+    fn registers_depend_on_the_machine() {
+        let config = MachineConfig::preset("minimal").unwrap();
+        assert_eq!(
+            WatchTarget::parse("R1", &config),
+            Some(WatchTarget::Register(1))
+        );
+        assert_eq!(
+            WatchTarget::parse("sp", &config),
+            Some(WatchTarget::Register(SP))
+        );
+        assert_eq!(WatchTarget::parse("r2", &config), None);
+        assert_eq!(WatchTarget::parse("r4", &MachineConfig::default()), None);
+    }
+
+    #[test]
+    fn overwrites_dont_read_the_destination() {
+        assert_eq!(
+            first_read("mov (0x800), 1\nmovb (0x800), 2\nstop\n", "0x800"),
+            None
+        );
+        assert_eq!(first_read("mov r0, 1\nread r0, p3\nstop\n", "r0"), None);
+    }
+
+    #[test]
+    fn updates_read_the_destination() {
+        assert_eq!(
+            first_read("mov (0x800), 1\nadd (0x800), 1\nstop\n", "0x800"),
+            Some(1)
+        );
+        assert_eq!(first_read("mov r0, 1\ninc r0\nstop\n", "r0"), Some(1));
+        assert_eq!(first_read("mov r1, 1\nxchg r0, r1\nstop\n", "r0"), Some(1));
+    }
+}